@@ -1,5 +1,6 @@
 use super::DUTY_CYCLES;
 use super::utils::{LengthCounter, SweepEnvelope, VolumeEnvelope};
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 #[derive(Default)]
@@ -17,6 +18,19 @@ pub struct Channel1 {
     dac_enabled: bool,
 }
 
+impl_savestate!(Channel1 {
+    duty,
+    period,
+    trigger,
+    duty_position,
+    period_counter,
+    frame_sequence,
+    length,
+    volume,
+    sweep,
+    dac_enabled,
+});
+
 impl Channel1 {
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
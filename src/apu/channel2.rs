@@ -1,5 +1,6 @@
 use super::DUTY_CYCLES;
 use super::utils::{LengthCounter, VolumeEnvelope};
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 #[derive(Default)]
@@ -16,6 +17,18 @@ pub struct Channel2 {
     dac_enabled: bool,
 }
 
+impl_savestate!(Channel2 {
+    duty,
+    period,
+    trigger,
+    duty_position,
+    period_counter,
+    frame_sequence,
+    length,
+    volume,
+    dac_enabled,
+});
+
 impl Channel2 {
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
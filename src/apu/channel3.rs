@@ -1,4 +1,5 @@
 use super::utils::LengthCounter;
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 #[derive(Default)]
@@ -13,6 +14,16 @@ pub struct Channel3 {
     length: LengthCounter<256>,
 }
 
+impl_savestate!(Channel3 {
+    dac_enabled,
+    volume,
+    period,
+    trigger,
+    sample_index,
+    period_counter,
+    length,
+});
+
 impl Channel3 {
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
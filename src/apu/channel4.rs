@@ -1,4 +1,5 @@
 use super::utils::{LengthCounter, VolumeEnvelope};
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 #[derive(Default)]
@@ -16,6 +17,19 @@ pub struct Channel4 {
     dac_enabled: bool,
 }
 
+impl_savestate!(Channel4 {
+    clock_divider,
+    lfsr_width,
+    clock_shift,
+    trigger,
+    lfsr,
+    period_counter,
+    frame_sequence,
+    length,
+    volume,
+    dac_enabled,
+});
+
 impl Channel4 {
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
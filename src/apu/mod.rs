@@ -1,18 +1,14 @@
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
-use std::sync::mpsc::{Receiver, Sender, channel};
-use std::time::{Duration, Instant};
-
-use anyhow::Result;
-use cpal::StreamConfig;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-
 mod channel1;
 mod channel2;
 mod channel3;
 mod channel4;
 mod utils;
 
+pub const SAMPLE_RATE: u32 = 48000;
+
 const DUTY_CYCLES: [u8; 4] = [
     0b00000001, // 12.5%
     0b00000011, // 25%
@@ -43,6 +39,13 @@ struct Panning {
     channel4: (bool, bool),
 }
 
+impl_savestate!(Panning {
+    channel1,
+    channel2,
+    channel3,
+    channel4,
+});
+
 impl Panning {
     fn new(val: u8) -> Self {
         Self {
@@ -65,14 +68,24 @@ impl Panning {
     }
 }
 
+impl_savestate!(Apu {
+    channel1,
+    channel2,
+    channel3,
+    channel4,
+    panning,
+    aram,
+    vin_left,
+    left_volume,
+    vin_right,
+    right_volume,
+    master_enable,
+});
+
 impl Apu {
-    pub fn new(volume: f32, disable_audio: bool) -> Self {
-        let (sample_tx, sample_rx) = channel();
-        if !disable_audio {
-            std::thread::spawn(move || spawn_audio(sample_rx, volume));
-        }
+    pub fn new() -> Self {
         Self {
-            sampler: Sampler::new(sample_tx),
+            sampler: Sampler::new(),
             channel1: Default::default(),
             channel2: Default::default(),
             channel3: Default::default(),
@@ -170,6 +183,12 @@ impl Apu {
             .push_sample((left_sample * left_vol, right_sample * right_vol));
     }
 
+    // The APU is clocked by the system, so it stops in STOP mode, but the
+    // host still expects samples
+    pub fn tick_silent(&mut self) {
+        self.sampler.push_sample((0.0, 0.0));
+    }
+
     fn sample(&self) -> (f32, f32) {
         macro_rules! pan {
             ($chan:ident $(,$arg:expr)?) => {{
@@ -199,90 +218,46 @@ impl Apu {
         self.channel4.tick_frame_sequencer();
     }
 
-    pub fn toggle_frame_limiter(&mut self) {
-        self.sampler.limit_framerate = !self.sampler.limit_framerate;
-    }
-}
-
-fn spawn_audio(sample_rx: Receiver<(f32, f32)>, volume: f32) -> Result<()> {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .ok_or_else(|| anyhow::Error::msg("Default output device is not available"))?;
-    let config = StreamConfig {
-        channels: 2,
-        sample_rate: cpal::SampleRate(48000),
-        buffer_size: cpal::BufferSize::Default,
-    };
-
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [f32], _| {
-            for frame in data.chunks_mut(2) {
-                if let Ok((left, right)) = sample_rx.recv() {
-                    frame[0] = left * volume / 100.0;
-                    frame[1] = right * volume / 100.0;
-                }
-            }
-        },
-        |err| eprintln!("{err}"),
-        None,
-    )?;
-    stream.play()?;
-    loop {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
+    pub fn drain_samples(&mut self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.sampler.samples.drain(..)
     }
 }
 
 struct Sampler {
-    sample_tx: Sender<(f32, f32)>,
     sample_buffer: Vec<(f32, f32)>,
-    instant: Instant,
-    limit_framerate: bool,
+    samples: Vec<(f32, f32)>,
 }
 
 impl Sampler {
-    fn new(sample_tx: Sender<(f32, f32)>) -> Self {
+    fn new() -> Self {
         Self {
-            sample_tx,
             sample_buffer: Vec::with_capacity(8192),
-            instant: Instant::now(),
-            limit_framerate: true,
+            samples: Vec::with_capacity(SAMPLE_RATE as usize),
         }
     }
 
     fn push_sample(&mut self, sample: (f32, f32)) {
-        if self.sample_buffer.len() < 8192 {
-            self.sample_buffer.push(sample);
-        }
-        if self.sample_buffer.len() == 8192
-            && (self.limit_framerate
-                || self.instant.elapsed() >= Duration::from_secs_f64(1.0 / 128.0))
-        {
+        self.sample_buffer.push(sample);
+        if self.sample_buffer.len() == 8192 {
             // 8192 samples @ 1048576Hz = 375 samples @ 48000Hz
             //
             // Interpolate 22 or 21 samples at a time.
             //   8192 = 317*22 + 58*21
             //   317 + 58 = 375
             let (h1, h2) = self.sample_buffer.split_at(317 * 22);
-            let samples: [(f32, f32); 375] = h1
-                .chunks_exact(22)
-                .chain(h2.chunks_exact(21))
-                .map(|slice| {
-                    let sum = slice
-                        .iter()
-                        .fold((0.0, 0.0), |acc, s| (acc.0 + s.0, acc.1 + s.1));
-                    let len = slice.len() as f32;
-                    (sum.0 / len, sum.1 / len)
-                })
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap();
-            for sample in samples {
-                let _ = self.sample_tx.send(sample);
+            let samples = h1.chunks_exact(22).chain(h2.chunks_exact(21)).map(|slice| {
+                let sum = slice
+                    .iter()
+                    .fold((0.0, 0.0), |acc, s| (acc.0 + s.0, acc.1 + s.1));
+                let len = slice.len() as f32;
+                (sum.0 / len, sum.1 / len)
+            });
+            // Drop samples once a second's worth is buffered, in case the
+            // frontend isn't draining them
+            if self.samples.len() < SAMPLE_RATE as usize {
+                self.samples.extend(samples);
             }
             self.sample_buffer.clear();
-            self.instant = Instant::now();
         }
     }
 }
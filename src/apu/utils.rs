@@ -1,3 +1,7 @@
+use crate::savestate::{Savestate, impl_savestate};
+
+use anyhow::Result;
+
 pub struct LengthCounter<const N: u16> {
     enable: bool,
     timer: u16,
@@ -40,6 +44,20 @@ impl<const N: u16> LengthCounter<N> {
     }
 }
 
+impl<const N: u16> Savestate for LengthCounter<N> {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.enable.save_state(state);
+        self.timer.save_state(state);
+        self.tick.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        self.enable.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.tick.load_state(state)
+    }
+}
+
 impl<const N: u16> Default for LengthCounter<N> {
     fn default() -> Self {
         Self {
@@ -60,6 +78,14 @@ pub struct VolumeEnvelope {
     pace_timer: u8,
 }
 
+impl_savestate!(VolumeEnvelope {
+    pace,
+    direction,
+    initial_level,
+    level,
+    pace_timer,
+});
+
 impl VolumeEnvelope {
     pub fn trigger(&mut self) {
         self.level = self.initial_level;
@@ -101,6 +127,16 @@ pub struct SweepEnvelope {
     negate_mode: bool,
 }
 
+impl_savestate!(SweepEnvelope {
+    step,
+    direction,
+    pace,
+    shadow_period,
+    pace_timer,
+    enabled,
+    negate_mode,
+});
+
 impl SweepEnvelope {
     pub fn trigger(&mut self, period: u16) -> bool {
         self.shadow_period = period;
@@ -0,0 +1,180 @@
+use super::CartridgeError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Enhanced,
+    Only,
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // Only present in later games, in the last 4 bytes of the title area
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub japanese: bool,
+    pub old_licensee: u8,
+    // Used when the old licensee code is 0x33
+    pub new_licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < 0x150 {
+            return Err(CartridgeError::Truncated(rom.len()));
+        }
+        let cgb = match rom[0x143] {
+            0xc0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // Older games use all 16 bytes for the title. CGB games shortened it
+        // to 15 to make room for the CGB flag, and some of those then used 4
+        // more for a manufacturer code.
+        let code = &rom[0x13f..0x143];
+        let (title, manufacturer_code) = match cgb {
+            CgbSupport::None => (&rom[0x134..0x144], None),
+            _ if code.iter().all(u8::is_ascii_uppercase) => (
+                &rom[0x134..0x13f],
+                Some(String::from_utf8_lossy(code).into_owned()),
+            ),
+            _ => (&rom[0x134..0x143], None),
+        };
+
+        let computed_header_checksum = rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+            .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16));
+
+        Ok(Self {
+            title: ascii_string(title),
+            manufacturer_code,
+            cgb,
+            sgb: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+            japanese: rom[0x14a] == 0x00,
+            old_licensee: rom[0x14b],
+            new_licensee: ascii_string(&rom[0x144..0x146]),
+            version: rom[0x14c],
+            header_checksum: rom[0x14d],
+            global_checksum: u16::from_be_bytes([rom[0x14e], rom[0x14f]]),
+            computed_header_checksum,
+            computed_global_checksum,
+        })
+    }
+
+    // The bootrom refuses to start games where this doesn't match
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    // Nothing checks this on real hardware, but a mismatch usually means a
+    // bad dump or a hacked ROM
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn rom_banks(&self) -> Option<u16> {
+        match self.rom_size {
+            0x00..=0x08 => Some(2 << self.rom_size),
+            _ => None,
+        }
+    }
+
+    pub fn ram_bytes(&self) -> Option<usize> {
+        let kb = match self.ram_size {
+            0x00 => 0,
+            0x01 => 2,
+            0x02 => 8,
+            0x03 => 32,
+            0x04 => 128,
+            0x05 => 64,
+            _ => return None,
+        };
+        Some(kb * 1024)
+    }
+
+    pub fn cartridge_type_name(&self) -> Option<&'static str> {
+        let name = match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.cartridge_type_name()
+            .is_some_and(|name| name.split('+').any(|part| part == feature))
+    }
+
+    // MBC2 has 512 half-bytes of RAM built in, and reports no RAM size
+    pub fn has_ram(&self) -> bool {
+        self.has_feature("RAM") || matches!(self.cartridge_type, 0x05 | 0x06)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_feature("BATTERY")
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.has_feature("TIMER")
+    }
+}
+
+// Header strings are padded with NULs, and some games put junk after them
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
@@ -1,9 +1,8 @@
 use anyhow::Result;
-use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
 
-use super::Mapper;
+use super::{Mapper, ram_offset};
+use crate::savestate::impl_savestate;
 
 pub struct MBC1 {
     rom: Vec<u8>,
@@ -13,6 +12,8 @@ pub struct MBC1 {
     mode: bool,
 }
 
+impl_savestate!(MBC1 { bank1, bank2, mode });
+
 impl MBC1 {
     pub fn new(rom: Vec<u8>, num_banks: u16) -> Self {
         Self {
@@ -42,6 +43,7 @@ impl Mapper for MBC1 {
         match addr {
             0x0000..=0x3fff => self.rom[self.lo_bank() * 0x4000 + addr as usize],
             0x4000..=0x7fff => self.rom[self.hi_bank() * 0x4000 + addr as usize - 0x4000],
+            0xa000..=0xbfff => 0xff,
             _ => unreachable!(),
         }
     }
@@ -52,7 +54,7 @@ impl Mapper for MBC1 {
             0x2000..=0x3fff => {
                 let val = val & 0x1f;
                 self.bank1 = if val != 0 {
-                    val % self.num_banks as u8
+                    (val as u16 % self.num_banks) as u8
                 } else {
                     1
                 };
@@ -65,6 +67,14 @@ impl Mapper for MBC1 {
             _ => unreachable!(),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 {
+            self.lo_bank()
+        } else {
+            self.hi_bank()
+        }
+    }
 }
 
 pub struct MBC1Ram {
@@ -74,6 +84,13 @@ pub struct MBC1Ram {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC1Ram {
+    mbc1,
+    ram,
+    ram_bank,
+    ram_enabled,
+});
+
 impl MBC1Ram {
     pub fn new(rom: Vec<u8>, num_banks: u16, ram_size: u32) -> Self {
         Self {
@@ -97,13 +114,10 @@ impl Mapper for MBC1Ram {
     fn read(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x7fff => self.mbc1.read(addr),
-            0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    self.ram[self.ram_bank() * 0x2000 + addr as usize - 0xa000]
-                } else {
-                    0xFF
-                }
-            }
+            0xa000..=0xbfff => match ram_offset(&self.ram, self.ram_bank(), addr) {
+                Some(offset) if self.ram_enabled => self.ram[offset],
+                _ => 0xFF,
+            },
             _ => unreachable!(),
         }
     }
@@ -115,32 +129,35 @@ impl Mapper for MBC1Ram {
             0x4000..=0x5fff => {
                 self.mbc1.write(addr, val);
                 if self.mbc1.mode {
-                    let num_ram_banks = self.ram.len().div_ceil(0x2000) as u8;
-                    self.ram_bank = (val & 0b11) % num_ram_banks;
+                    self.ram_bank = val & 0b11;
                 }
             }
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let ram_addr = self.ram_bank() * 0x2000 + addr as usize - 0xa000;
-                    if ram_addr < self.ram.len() {
-                        self.ram[ram_addr] = val;
-                    }
+                if self.ram_enabled
+                    && let Some(offset) = ram_offset(&self.ram, self.ram_bank(), addr)
+                {
+                    self.ram[offset] = val;
                 }
             }
             _ => unreachable!(),
         }
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(&self.ram)?;
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc1.rom_bank(addr)
+    }
+
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.ram)?;
         Ok(())
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            file.read_exact(&mut self.ram)?;
-        }
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        reader.read_exact(&mut self.ram)?;
         Ok(())
     }
 }
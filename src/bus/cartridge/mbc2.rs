@@ -1,9 +1,8 @@
 use anyhow::Result;
-use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
 
 use super::Mapper;
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 pub struct MBC2 {
@@ -14,6 +13,12 @@ pub struct MBC2 {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC2 {
+    bank,
+    ram,
+    ram_enabled
+});
+
 impl MBC2 {
     pub fn new(rom: Vec<u8>, num_banks: u16) -> Self {
         Self {
@@ -48,7 +53,7 @@ impl Mapper for MBC2 {
                 if addr.bit(8) {
                     let val = val & 0xf;
                     self.bank = if val != 0 {
-                        val % self.num_banks as u8
+                        (val as u16 % self.num_banks) as u8
                     } else {
                         1
                     }
@@ -66,16 +71,21 @@ impl Mapper for MBC2 {
         }
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(self.ram.as_slice())?;
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.bank as usize }
+    }
+
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(self.ram.as_slice())?;
         Ok(())
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            file.read_exact(self.ram.as_mut_slice())?;
-        }
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        reader.read_exact(self.ram.as_mut_slice())?;
         Ok(())
     }
 }
@@ -1,19 +1,26 @@
 use anyhow::Result;
-use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::Mapper;
+use super::{Mapper, ram_offset};
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
 pub struct MBC3 {
     rom: Vec<u8>,
+    num_banks: u16,
     bank: u8,
 }
 
+impl_savestate!(MBC3 { bank });
+
 impl MBC3 {
-    pub fn new(rom: Vec<u8>) -> Self {
-        Self { rom, bank: 1 }
+    pub fn new(rom: Vec<u8>, num_banks: u16) -> Self {
+        Self {
+            rom,
+            num_banks,
+            bank: 1,
+        }
     }
 }
 
@@ -22,16 +29,31 @@ impl Mapper for MBC3 {
         match addr {
             0x0000..=0x3fff => self.rom[addr as usize],
             0x4000..=0x7fff => self.rom[self.bank as usize * 0x4000 + addr as usize - 0x4000],
+            0xa000..=0xbfff => 0xff,
             _ => unreachable!(),
         }
     }
 
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0x2000..=0x3fff => self.bank = if val == 0 { 1 } else { val },
-            _ => panic!("MBC3 Write: ${addr:04x} = {val:02x}"),
+            0x2000..=0x3fff => {
+                let val = val & 0x7f;
+                self.bank = if val != 0 {
+                    (val as u16 % self.num_banks) as u8
+                } else {
+                    1
+                };
+            }
+            // RAM enable, RAM bank select and RTC latch go nowhere without
+            // RAM or an RTC
+            0x0000..=0x1fff | 0x4000..=0x7fff | 0xa000..=0xbfff => {}
+            _ => unreachable!(),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.bank as usize }
+    }
 }
 
 pub struct MBC3Ram {
@@ -41,10 +63,17 @@ pub struct MBC3Ram {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC3Ram {
+    mbc3,
+    ram,
+    ram_bank,
+    ram_enabled,
+});
+
 impl MBC3Ram {
-    pub fn new(rom: Vec<u8>, ram_size: u32) -> Self {
+    pub fn new(rom: Vec<u8>, num_banks: u16, ram_size: u32) -> Self {
         Self {
-            mbc3: MBC3::new(rom),
+            mbc3: MBC3::new(rom, num_banks),
             ram: vec![0; ram_size as usize],
             ram_bank: 0,
             ram_enabled: false,
@@ -52,17 +81,24 @@ impl MBC3Ram {
     }
 }
 
+impl MBC3Ram {
+    // RTC register selects leave nothing mapped
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        match self.ram_bank {
+            0x00..=0x03 => ram_offset(&self.ram, self.ram_bank as usize, addr),
+            _ => None,
+        }
+    }
+}
+
 impl Mapper for MBC3Ram {
     fn read(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x7fff => self.mbc3.read(addr),
-            0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    self.ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000]
-                } else {
-                    0xFF
-                }
-            }
+            0xa000..=0xbfff => match self.ram_offset(addr) {
+                Some(offset) if self.ram_enabled => self.ram[offset],
+                _ => 0xFF,
+            },
             _ => unreachable!(),
         }
     }
@@ -72,32 +108,38 @@ impl Mapper for MBC3Ram {
             0x0000..=0x1fff => self.ram_enabled = (val & 0xf) == 0xA,
             0x2000..=0x3fff => self.mbc3.write(addr, val),
             0x4000..=0x5fff => {
-                if val <= 0x03 {
-                    self.ram_bank = val & 0b11;
-                } else {
-                    panic!("Invalid MBC3 ram bank: {val:02x}")
+                if val > 0x03 {
+                    log::warn!("MBC3: selected RAM bank {val:02x}, but there's no RTC");
                 }
+                self.ram_bank = val;
             }
             0x6000..=0x7fff => {}
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    self.ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000] = val;
+                if self.ram_enabled
+                    && let Some(offset) = self.ram_offset(addr)
+                {
+                    self.ram[offset] = val;
                 }
             }
             _ => unreachable!(),
         }
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(&self.ram)?;
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc3.rom_bank(addr)
+    }
+
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.ram)?;
         Ok(())
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            file.read_exact(&mut self.ram)?;
-        }
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        reader.read_exact(&mut self.ram)?;
         Ok(())
     }
 }
@@ -109,10 +151,17 @@ pub struct MBC3Rtc {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC3Rtc {
+    mbc3,
+    rtc,
+    rtc_register,
+    ram_enabled,
+});
+
 impl MBC3Rtc {
-    pub fn new(rom: Vec<u8>) -> Self {
+    pub fn new(rom: Vec<u8>, num_banks: u16) -> Self {
         Self {
-            mbc3: MBC3::new(rom),
+            mbc3: MBC3::new(rom, num_banks),
             rtc: Rtc::default(),
             rtc_register: 0,
             ram_enabled: false,
@@ -159,26 +208,24 @@ impl Mapper for MBC3Rtc {
         }
     }
 
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc3.rom_bank(addr)
+    }
+
     fn increment_rtc(&mut self) {
         self.rtc.increment();
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(&self.rtc.internal_state.as_bytes())?;
-        file.write_all(&self.rtc.latched_state.as_bytes())?;
-        Ok(())
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        self.rtc.save(writer)
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            let mut bytes = [0; 5];
-            file.read_exact(&mut bytes)?;
-            self.rtc.internal_state = RtcState::from_bytes(bytes);
-            file.read_exact(&mut bytes)?;
-            self.rtc.latched_state = RtcState::from_bytes(bytes);
-        }
-        Ok(())
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.rtc.load(reader)
     }
 }
 
@@ -190,10 +237,18 @@ pub struct MBC3RamRtc {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC3RamRtc {
+    mbc3,
+    ram,
+    rtc,
+    register,
+    ram_enabled,
+});
+
 impl MBC3RamRtc {
-    pub fn new(rom: Vec<u8>, ram_size: u32) -> Self {
+    pub fn new(rom: Vec<u8>, num_banks: u16, ram_size: u32) -> Self {
         Self {
-            mbc3: MBC3::new(rom),
+            mbc3: MBC3::new(rom, num_banks),
             ram: vec![0; ram_size as usize],
             rtc: Rtc::default(),
             register: 0,
@@ -210,7 +265,8 @@ impl Mapper for MBC3RamRtc {
                 if self.ram_enabled {
                     let reg = self.register & 0xf;
                     match reg {
-                        0x00..=0x03 => self.ram[reg as usize * 0x2000 + addr as usize - 0xa000],
+                        0x00..=0x03 => ram_offset(&self.ram, reg as usize, addr)
+                            .map_or(0xFF, |offset| self.ram[offset]),
                         0x08..=0x0c => self.rtc.read(reg),
                         _ => 0xFF,
                     }
@@ -242,7 +298,9 @@ impl Mapper for MBC3RamRtc {
                     let reg = self.register & 0xf;
                     match reg {
                         0x00..=0x03 => {
-                            self.ram[reg as usize * 0x2000 + addr as usize - 0xa000] = val;
+                            if let Some(offset) = ram_offset(&self.ram, reg as usize, addr) {
+                                self.ram[offset] = val;
+                            }
                         }
                         0x08..=0x0c => self.rtc.write(reg, val),
                         _ => {}
@@ -253,29 +311,26 @@ impl Mapper for MBC3RamRtc {
         }
     }
 
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc3.rom_bank(addr)
+    }
+
     fn increment_rtc(&mut self) {
         self.rtc.increment();
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(&self.ram)?;
-        file.write_all(&self.rtc.internal_state.as_bytes())?;
-        file.write_all(&self.rtc.latched_state.as_bytes())?;
-        Ok(())
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.ram)?;
+        self.rtc.save(writer)
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            file.read_exact(&mut self.ram)?;
-
-            let mut bytes = [0; 5];
-            file.read_exact(&mut bytes)?;
-            self.rtc.internal_state = RtcState::from_bytes(bytes);
-            file.read_exact(&mut bytes)?;
-            self.rtc.latched_state = RtcState::from_bytes(bytes);
-        }
-        Ok(())
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        reader.read_exact(&mut self.ram)?;
+        self.rtc.load(reader)
     }
 }
 
@@ -287,20 +342,29 @@ struct RtcState {
     days: u16,
 }
 
+impl_savestate!(RtcState {
+    seconds,
+    minutes,
+    hours,
+    days,
+});
+
 impl RtcState {
-    fn from_bytes(bytes: [u8; 5]) -> Self {
-        let [seconds, minutes, hours, days_hi, days_lo] = bytes;
-        Self {
-            seconds,
-            minutes,
-            hours,
-            days: u16::from_be_bytes([days_hi, days_lo]),
-        }
+    fn total_seconds(&self) -> u64 {
+        self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
     }
 
-    fn as_bytes(&self) -> [u8; 5] {
-        let [days_hi, days_lo] = self.days.to_be_bytes();
-        [self.seconds, self.minutes, self.hours, days_lo, days_hi]
+    // Returns whether the day counter overflowed
+    fn set_total_seconds(&mut self, seconds: u64) -> bool {
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (seconds / 60 % 60) as u8;
+        self.hours = (seconds / 3600 % 24) as u8;
+        let days = seconds / 86400;
+        self.days = (days % 512) as u16;
+        days >= 512
     }
 }
 
@@ -315,7 +379,98 @@ pub struct Rtc {
     latched_state: RtcState,
 }
 
+impl_savestate!(Rtc {
+    prepare_latch,
+    carry,
+    halted,
+    cycles,
+    ticks,
+    internal_state,
+    latched_state,
+});
+
 impl Rtc {
+    // The footer VBA and BGB append to the save file: the current and latched
+    // registers (S, M, H, DL, DH) as little-endian u32s, followed by the UNIX
+    // time of the save as a u64.
+    fn save(&self, writer: &mut dyn Write) -> Result<()> {
+        for state in [&self.internal_state, &self.latched_state] {
+            let dh =
+                ((self.carry as u8) << 7) | ((self.halted as u8) << 6) | (state.days >> 8) as u8;
+            for reg in [
+                state.seconds,
+                state.minutes,
+                state.hours,
+                state.days as u8,
+                dh,
+            ] {
+                writer.write_all(&(reg as u32).to_le_bytes())?;
+            }
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writer.write_all(&timestamp.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Also accepts the 44-byte variant with a 32-bit timestamp, and the footer
+    // older versions of rgb wrote. The clock is advanced by the real time that
+    // passed since the save, unless halted.
+    fn load(&mut self, reader: &mut dyn Read) -> Result<()> {
+        let mut footer = Vec::new();
+        reader.read_to_end(&mut footer)?;
+        let timestamp = match footer.len() {
+            48 => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            44 => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            10 => {
+                self.load_legacy(&footer);
+                return Ok(());
+            }
+            // Saved by an emulator without RTC support, start from zero
+            0 => return Ok(()),
+            len => {
+                log::warn!("Ignoring an RTC footer of unknown length {len}");
+                return Ok(());
+            }
+        };
+        let regs: Vec<u8> = footer[..40].chunks(4).map(|reg| reg[0]).collect();
+        for (state, regs) in [&mut self.internal_state, &mut self.latched_state]
+            .into_iter()
+            .zip(regs.chunks(5))
+        {
+            state.seconds = regs[0] & 0x3f;
+            state.minutes = regs[1] & 0x3f;
+            state.hours = regs[2] & 0x1f;
+            state.days = u16::from_le_bytes([regs[3], regs[4] & 1]);
+        }
+        self.halted = regs[4].bit(6);
+        self.carry = regs[4].bit(7);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if !self.halted && now > timestamp {
+            let elapsed = now - timestamp;
+            let seconds = self.internal_state.total_seconds() + elapsed;
+            if self.internal_state.set_total_seconds(seconds) {
+                self.carry = true;
+            }
+        }
+        Ok(())
+    }
+
+    // The current and latched S, M, H, days low and days high registers, with
+    // no DH flags and no timestamp, so the clock just continues from there.
+    // The next save is in the VBA/BGB format.
+    fn load_legacy(&mut self, footer: &[u8]) {
+        for (state, regs) in [&mut self.internal_state, &mut self.latched_state]
+            .into_iter()
+            .zip(footer.chunks(5))
+        {
+            state.seconds = regs[0] & 0x3f;
+            state.minutes = regs[1] & 0x3f;
+            state.hours = regs[2] & 0x1f;
+            state.days = u16::from_le_bytes([regs[3], regs[4] & 1]);
+        }
+    }
+
     pub fn read(&self, register: u8) -> u8 {
         match register {
             0x08 => self.latched_state.seconds,
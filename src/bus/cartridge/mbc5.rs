@@ -1,9 +1,8 @@
 use anyhow::Result;
-use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
 
-use super::Mapper;
+use super::{Mapper, ram_offset};
+use crate::savestate::impl_savestate;
 
 pub struct MBC5 {
     rom: Vec<u8>,
@@ -11,6 +10,8 @@ pub struct MBC5 {
     bank: u16,
 }
 
+impl_savestate!(MBC5 { bank });
+
 impl MBC5 {
     pub fn new(rom: Vec<u8>, num_banks: u16) -> Self {
         Self {
@@ -26,6 +27,7 @@ impl Mapper for MBC5 {
         match addr {
             0x0000..=0x3fff => self.rom[addr as usize],
             0x4000..=0x7fff => self.rom[self.bank as usize * 0x4000 + addr as usize - 0x4000],
+            0xa000..=0xbfff => 0xff,
             _ => unreachable!(),
         }
     }
@@ -37,10 +39,14 @@ impl Mapper for MBC5 {
             0x3000..=0x3fff => {
                 self.bank = (((val as u16 & 1) << 8) | (self.bank & 0xff)) % self.num_banks;
             }
-            0x4000..=0x7fff => {}
+            0x4000..=0x7fff | 0xa000..=0xbfff => {}
             _ => unreachable!(),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.bank as usize }
+    }
 }
 
 pub struct MBC5Ram {
@@ -50,6 +56,13 @@ pub struct MBC5Ram {
     ram_enabled: bool,
 }
 
+impl_savestate!(MBC5Ram {
+    mbc5,
+    ram,
+    ram_bank,
+    ram_enabled,
+});
+
 impl MBC5Ram {
     pub fn new(rom: Vec<u8>, num_banks: u16, ram_size: u32) -> Self {
         Self {
@@ -65,13 +78,10 @@ impl Mapper for MBC5Ram {
     fn read(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x7fff => self.mbc5.read(addr),
-            0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    self.ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000]
-                } else {
-                    0xFF
-                }
-            }
+            0xa000..=0xbfff => match ram_offset(&self.ram, self.ram_bank as usize, addr) {
+                Some(offset) if self.ram_enabled => self.ram[offset],
+                _ => 0xFF,
+            },
             _ => unreachable!(),
         }
     }
@@ -80,30 +90,34 @@ impl Mapper for MBC5Ram {
         match addr {
             0x0000..=0x1fff => self.ram_enabled = (val & 0xf) == 0xA,
             0x2000..=0x3fff => self.mbc5.write(addr, val),
-            0x4000..=0x5fff => {
-                let num_ram_banks = self.ram.len().div_ceil(0x2000) as u8;
-                self.ram_bank = (val & 0b1111) % num_ram_banks;
-            }
+            0x4000..=0x5fff => self.ram_bank = val & 0b1111,
             0x6000..=0x7fff => {}
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    self.ram[self.ram_bank as usize * 0x2000 + addr as usize - 0xa000] = val;
+                if self.ram_enabled
+                    && let Some(offset) = ram_offset(&self.ram, self.ram_bank as usize, addr)
+                {
+                    self.ram[offset] = val;
                 }
             }
             _ => unreachable!(),
         }
     }
 
-    fn save_external_ram(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(&self.ram)?;
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc5.rom_bank(addr)
+    }
+
+    fn save_external_ram(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.ram)?;
         Ok(())
     }
 
-    fn load_external_ram(&mut self, filename: &Path) -> Result<()> {
-        if let Ok(mut file) = File::open(filename) {
-            file.read_exact(&mut self.ram)?;
-        }
+    fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        reader.read_exact(&mut self.ram)?;
         Ok(())
     }
 }
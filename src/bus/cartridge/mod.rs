@@ -1,92 +1,139 @@
 use anyhow::Result;
-use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use thiserror::Error;
 
+mod header;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
 
+pub use header::{CartridgeHeader, CgbSupport};
 use mbc1::{MBC1, MBC1Ram};
 use mbc2::MBC2;
 use mbc3::{MBC3, MBC3Ram, MBC3RamRtc, MBC3Rtc};
 use mbc5::{MBC5, MBC5Ram};
 
-trait Mapper {
+use crate::savestate::{Savestate, impl_savestate};
+
+trait Mapper: Savestate {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
 
+    // ROM bank mapped at the given address in 0x0000-0x7fff
+    fn rom_bank(&self, addr: u16) -> usize {
+        addr as usize / 0x4000
+    }
+
     fn increment_rtc(&mut self) {}
 
-    fn save_external_ram(&self, _: &Path) -> Result<()> {
+    // Whether external RAM (or the RTC) is currently enabled for access
+    fn ram_enabled(&self) -> bool {
+        false
+    }
+
+    fn save_external_ram(&self, _: &mut dyn Write) -> Result<()> {
         Ok(())
     }
 
-    fn load_external_ram(&mut self, _: &Path) -> Result<()> {
+    fn load_external_ram(&mut self, _: &mut dyn Read) -> Result<()> {
         Ok(())
     }
 }
 
+// Offset into external RAM of an access to 0xa000-0xbfff with `bank`
+// selected. Banks past the end of RAM mirror it, as does a 2 KiB RAM within
+// its bank. None if there's no RAM at all.
+fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    (!ram.is_empty()).then(|| (bank * 0x2000 + addr as usize - 0xa000) % ram.len())
+}
+
 struct NoMapper {
     rom: Box<[u8; 0x8000]>,
 }
 
+impl Savestate for NoMapper {
+    fn save_state(&self, _: &mut Vec<u8>) {}
+
+    fn load_state(&mut self, _: &mut &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
 impl Mapper for NoMapper {
     fn read(&self, addr: u16) -> u8 {
-        self.rom[addr as usize]
+        match addr {
+            0x0000..=0x7fff => self.rom[addr as usize],
+            _ => 0xff,
+        }
     }
 
     fn write(&mut self, _addr: u16, _val: u8) {}
 }
 
+#[derive(Debug, Error)]
+pub enum CartridgeError {
+    #[error("ROM is only {0:#x} bytes, too short to contain a header")]
+    Truncated(usize),
+    #[error("Unsupported mapper: {0:#04x}")]
+    UnsupportedMapper(u8),
+    #[error("Invalid ROM size in header: {0:#04x}")]
+    BadRomSize(u8),
+    #[error("Invalid RAM size in header: {0:#04x}")]
+    BadRamSize(u8),
+    #[error("ROM is {file:#x} bytes, but the header says {header:#x}")]
+    SizeMismatch { header: usize, file: usize },
+}
+
 pub struct Cartridge {
     mapper: Box<dyn Mapper>,
-    save_path: PathBuf,
+    global_checksum: u16,
+    battery: bool,
+    // External RAM was written since the last save
+    ram_dirty: bool,
+    // The game disabled RAM after writing to it, which is a good moment to save
+    flush_requested: bool,
 }
 
-impl Cartridge {
-    pub fn new(rom_path: PathBuf, saves_dir: PathBuf) -> Result<Self> {
-        let rom = std::fs::read(&rom_path)?;
-        let rom_name = rom_path.file_stem().unwrap();
-
-        std::fs::create_dir_all(&saves_dir)?;
-        let mut save_path = saves_dir;
-        save_path.push(rom_name);
-        save_path.set_extension("sav");
-
-        let mbc = rom[0x147];
-        let rom_type = rom[0x148];
-        let ram_type = rom[0x149];
-
-        let num_banks = match rom_type {
-            0x00..=0x08 => 2 << rom_type,
-            _ => unreachable!(),
-        };
-        let ram_size_kb = match ram_type {
-            0x00 => 0,
-            0x01 => 2,
-            0x02 => 8,
-            0x03 => 32,
-            0x04 => 128,
-            0x05 => 16,
-            _ => unreachable!(),
-        };
+impl_savestate!(Cartridge { mapper });
 
-        let mapper: Box<dyn Mapper> = match mbc {
-            0x00 => Box::new(NoMapper {
-                rom: rom.try_into().unwrap(),
-            }),
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let num_banks = header
+            .rom_banks()
+            .ok_or(CartridgeError::BadRomSize(header.rom_size))?;
+        let ram_size = header
+            .ram_bytes()
+            .ok_or(CartridgeError::BadRamSize(header.ram_size))? as u32;
+        let mut rom = resize_rom(rom, num_banks as usize * 0x4000)?;
+
+        let mapper: Box<dyn Mapper> = match header.cartridge_type {
+            0x00 => {
+                // Without a mapper only the first 32 KiB is addressable
+                rom.truncate(0x8000);
+                Box::new(NoMapper {
+                    rom: rom.try_into().unwrap(),
+                })
+            }
             0x01 => Box::new(MBC1::new(rom, num_banks)),
-            0x02 | 0x03 => Box::new(MBC1Ram::new(rom, num_banks, 1024 * ram_size_kb)),
+            0x02 | 0x03 => Box::new(MBC1Ram::new(rom, num_banks, ram_size)),
             0x05 | 0x06 => Box::new(MBC2::new(rom, num_banks)),
-            0x0f => Box::new(MBC3Rtc::new(rom)),
-            0x10 => Box::new(MBC3RamRtc::new(rom, 1024 * ram_size_kb)),
-            0x11 => Box::new(MBC3::new(rom)),
-            0x12 | 0x13 => Box::new(MBC3Ram::new(rom, 1024 * ram_size_kb)),
+            0x0f => Box::new(MBC3Rtc::new(rom, num_banks)),
+            0x10 => Box::new(MBC3RamRtc::new(rom, num_banks, ram_size)),
+            0x11 => Box::new(MBC3::new(rom, num_banks)),
+            0x12 | 0x13 => Box::new(MBC3Ram::new(rom, num_banks, ram_size)),
             0x19 => Box::new(MBC5::new(rom, num_banks)),
-            0x1a | 0x1b => Box::new(MBC5Ram::new(rom, num_banks, 1024 * ram_size_kb)),
-            _ => panic!("Invalid mapper value: {mbc:02x}"),
+            0x1a | 0x1b => Box::new(MBC5Ram::new(rom, num_banks, ram_size)),
+            mbc => return Err(CartridgeError::UnsupportedMapper(mbc)),
         };
-        Ok(Self { mapper, save_path })
+        Ok(Self {
+            mapper,
+            global_checksum: header.global_checksum,
+            battery: header.has_battery(),
+            ram_dirty: false,
+            flush_requested: false,
+        })
     }
 
     pub fn read(&self, addr: u16) -> u8 {
@@ -94,18 +141,77 @@ impl Cartridge {
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
+        if (0xa000..=0xbfff).contains(&addr) && self.battery {
+            self.ram_dirty = true;
+        }
+        let was_enabled = self.mapper.ram_enabled();
         self.mapper.write(addr, val);
+        if self.ram_dirty && was_enabled && !self.mapper.ram_enabled() {
+            self.flush_requested = true;
+        }
     }
 
     pub fn increment_rtc(&mut self) {
         self.mapper.increment_rtc();
     }
 
-    pub fn save_external_ram(&self) -> Result<()> {
-        self.mapper.save_external_ram(&self.save_path)
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.mapper.rom_bank(addr)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn take_flush_request(&mut self) -> bool {
+        std::mem::take(&mut self.flush_requested)
     }
 
-    pub fn load_external_ram(&mut self) -> Result<()> {
-        self.mapper.load_external_ram(&self.save_path)
+    // Without a battery, RAM contents don't survive power off, so there's
+    // nothing to save or load
+    pub fn save_external_ram(&mut self, writer: &mut dyn Write) -> Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        self.mapper.save_external_ram(writer)?;
+        self.ram_dirty = false;
+        self.flush_requested = false;
+        Ok(())
+    }
+
+    pub fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        self.mapper.load_external_ram(reader)
+    }
+
+    pub fn global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+}
+
+// Brings the ROM to the size given in the header. Short dumps are padded to a
+// power of two and then mirrored, the way a smaller ROM chip would respond.
+// Overdumps are accepted as long as the extra data only mirrors the ROM.
+fn resize_rom(mut rom: Vec<u8>, size: usize) -> Result<Vec<u8>, CartridgeError> {
+    if rom.len() > size {
+        if rom.chunks(size).any(|chunk| chunk != &rom[..chunk.len()]) {
+            return Err(CartridgeError::SizeMismatch {
+                header: size,
+                file: rom.len(),
+            });
+        }
+        rom.truncate(size);
+    } else if rom.len() < size {
+        rom.resize(rom.len().next_power_of_two(), 0xff);
+        while rom.len() < size {
+            rom.extend_from_within(..);
+        }
     }
+    Ok(rom)
 }
@@ -0,0 +1,56 @@
+use super::Bus;
+use super::watchpoint::Access;
+
+// 64 KiB of plain RAM with nothing else attached, for running the CPU on its
+// own. Every access is recorded along with the M-cycle it happened on.
+pub struct FlatBus {
+    pub ram: Box<[u8; 0x10000]>,
+    // One entry per M-cycle, `None` for cycles without a memory access
+    pub cycles: Vec<Option<(u16, u8, Access)>>,
+    access: Option<(u16, u8, Access)>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self {
+            ram: vec![0; 0x10000].try_into().unwrap(),
+            cycles: Vec::new(),
+            access: None,
+        }
+    }
+}
+
+impl FlatBus {
+    fn record(&mut self, addr: u16, val: u8, access: Access) {
+        assert!(
+            self.access.is_none(),
+            "more than one memory access in an M-cycle"
+        );
+        self.access = Some((addr, val, access));
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+        self.record(addr, val, Access::Read);
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+        self.record(addr, val, Access::Write);
+    }
+
+    fn tick(&mut self) {
+        self.cycles.push(self.access.take());
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.ram[0xff0f] & self.ram[0xffff] & 0x1f
+    }
+
+    fn acknowledge_interrupt(&mut self, int: u8) {
+        self.ram[0xff0f] &= !(1 << int);
+    }
+}
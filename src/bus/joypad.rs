@@ -1,6 +1,18 @@
-use crate::hotkeys::JoypadButton;
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 
+#[derive(Copy, Clone)]
+pub enum JoypadButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+}
+
 pub struct Joypad {
     up: bool,
     down: bool,
@@ -37,6 +49,14 @@ impl Default for Joypad {
     }
 }
 
+// Button states come from the host and are deliberately left out, so that
+// loading a state doesn't leave keys stuck down.
+impl_savestate!(Joypad {
+    buttons,
+    dpad,
+    interrupt,
+});
+
 impl Joypad {
     pub fn poll(&mut self) -> bool {
         if self.interrupt {
@@ -65,6 +85,11 @@ impl Joypad {
         }
     }
 
+    // Whether a button in a selected group is held
+    pub fn line_low(&self) -> bool {
+        self.read_nibble() != 0xF
+    }
+
     pub fn read(&self) -> u8 {
         0xC0 | ((!self.buttons as u8) << 5) | ((!self.dpad as u8) << 4) | self.read_nibble()
     }
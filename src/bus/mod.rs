@@ -1,11 +1,62 @@
 mod cartridge;
+pub mod flat;
 pub mod joypad;
+pub mod serial;
+pub mod watchpoint;
 
 use crate::apu::Apu;
-use crate::ppu::Ppu;
+use crate::model::Model;
+use crate::ppu::{OamCorruption, Ppu};
+use crate::savestate::impl_savestate;
 use crate::utils::BitExtract;
 pub use cartridge::*;
 use joypad::Joypad;
+use serial::Serial;
+use watchpoint::{Access, WatchHit, Watchpoint, Watchpoints};
+
+// Everything the CPU talks to. The CPU calls `tick` once per M-cycle, after
+// any read or write made during that cycle.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    fn tick(&mut self);
+
+    // Interrupts that are both requested and enabled (IF & IE)
+    fn pending_interrupts(&self) -> u8;
+    fn acknowledge_interrupt(&mut self, int: u8);
+
+    // Called before each instruction is fetched
+    fn begin_instruction(&mut self, _pc: u16) {}
+
+    // The CPU's 16-bit increment/decrement unit put `addr` on the address
+    // bus this M-cycle (INC rr, DEC rr, PUSH, POP, LD (HL+) and so on)
+    fn increment_address(&mut self, _addr: u16) {}
+
+    // The STOP instruction. What it does depends on the joypad, pending
+    // interrupts and, for the CGB speed switch, IME.
+    fn stop(&mut self, _ime: bool) -> Stop {
+        Stop::Continue
+    }
+
+    // Whether the system clock is stopped, either in STOP mode or during a
+    // speed switch. The CPU does nothing until it restarts.
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+// How the CPU carries on after STOP
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Continue,
+    // STOP acts like HALT when a button is held
+    Halt,
+    // The CPU misbehaves unpredictably, here it locks up
+    Hang,
+}
+
+// The CPU is paused for this long after a CGB speed switch
+const SPEED_SWITCH_MCYCLES: u16 = 2050;
 
 pub struct Timers {
     div: u16,
@@ -33,12 +84,27 @@ impl Default for Timers {
     }
 }
 
+impl_savestate!(Timers {
+    div,
+    tima,
+    tma,
+    tac,
+    result,
+    overflow,
+    reload,
+});
+
 impl Timers {
-    pub fn increment(&mut self, apu: &mut Apu) -> bool {
+    pub fn reset_div(&mut self) {
+        self.div = 0;
+    }
+
+    pub fn increment(&mut self, apu: &mut Apu, double_speed: bool) -> bool {
         let old_div = self.div;
         self.div = self.div.wrapping_add(4);
-        // Tick Apu FS on falling edge of bit 12
-        if old_div.bit(12) && !self.div.bit(12) {
+        // Tick Apu FS on falling edge of bit 12 (bit 13 in double speed mode)
+        let fs_bit = if double_speed { 13 } else { 12 };
+        if old_div.bit(fs_bit) && !self.div.bit(fs_bit) {
             apu.tick_frame_sequencer();
         }
         let bit = match self.tac & 0b11 {
@@ -73,52 +139,172 @@ impl Timers {
 }
 
 pub struct MemoryBus {
-    bootrom: Option<[u8; 0x100]>,
+    model: Model,
+    bootrom: Option<Vec<u8>>,
     pub cartridge: Cartridge,
     ppu: Ppu,
     dma: Dma,
+    hdma: Hdma,
     pub apu: Apu,
-    wram: Box<[u8; 0x2000]>,
+    wram: Box<[u8; 0x8000]>,
+    wram_bank: u8,
     hram: Box<[u8; 0x7f]>,
     pub timers: Timers,
     pub joypad: Joypad,
+    pub serial: Serial,
     bootrom_enabled: bool,
+    pub double_speed: bool,
+    pub speed_switch: bool,
     pub int_flag: u8,
     pub int_enable: u8,
+    // Whether the PPU, APU and RTC skip this M-cycle in double speed mode
+    odd_cycle: bool,
+    stop_mode: bool,
+    speed_switch_delay: u16,
+    // OAM addresses put on the bus this M-cycle, for the DMG OAM bug. Always
+    // cleared by the end of an instruction, so not savestated.
+    oam_bug: OamBugAccess,
+    watchpoints: Option<Box<Watchpoints>>,
+}
+
+#[derive(Copy, Clone, Default)]
+struct OamBugAccess {
+    read: bool,
+    write: bool,
+    increment: bool,
+}
+
+impl OamBugAccess {
+    fn corruption(self) -> Option<OamCorruption> {
+        match self {
+            Self {
+                read: true,
+                increment: true,
+                ..
+            } => Some(OamCorruption::ReadDuringIncrease),
+            Self { read: true, .. } => Some(OamCorruption::Read),
+            // An increment corrupts like a write, and a write with an
+            // increment like a single one
+            Self { write: true, .. }
+            | Self {
+                increment: true, ..
+            } => Some(OamCorruption::Write),
+            _ => None,
+        }
+    }
+}
+
+// Includes the unusable area after OAM
+fn is_oam_bug_address(addr: u16) -> bool {
+    (0xfe00..=0xfeff).contains(&addr)
 }
 
+impl_savestate!(MemoryBus {
+    cartridge,
+    ppu,
+    dma,
+    hdma,
+    apu,
+    wram,
+    wram_bank,
+    hram,
+    timers,
+    joypad,
+    serial,
+    bootrom_enabled,
+    double_speed,
+    speed_switch,
+    int_flag,
+    int_enable,
+    odd_cycle,
+    stop_mode,
+    speed_switch_delay,
+});
+
 impl MemoryBus {
-    pub fn new(bootrom: Option<[u8; 0x100]>, cartridge: Cartridge, apu: Apu) -> Self {
+    pub fn new(model: Model, bootrom: Option<Vec<u8>>, cartridge: Cartridge, apu: Apu) -> Self {
         Self {
+            model,
+            bootrom_enabled: bootrom.is_some(),
             bootrom,
             cartridge,
             apu,
-            ppu: Ppu::new(),
+            ppu: Ppu::new(model),
             dma: Dma::default(),
-            wram: vec![0; 0x2000].try_into().unwrap(),
+            hdma: Hdma::default(),
+            wram: vec![0; 0x8000].try_into().unwrap(),
+            wram_bank: 0,
             hram: vec![0; 0x7f].try_into().unwrap(),
             timers: Timers::default(),
             joypad: Joypad::default(),
-            bootrom_enabled: bootrom.is_some(),
+            serial: Serial::new(model),
+            double_speed: false,
+            speed_switch: false,
             int_flag: 0xE0,
             int_enable: 0,
+            odd_cycle: false,
+            stop_mode: false,
+            speed_switch_delay: 0,
+            oam_bug: OamBugAccess::default(),
+            watchpoints: None,
         }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        let val = self.dma_conflict(addr).unwrap_or_else(|| self.peek(addr));
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.check(Access::Read, addr, val, val);
+        }
+        val
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        if self.watchpoints.is_some() {
+            let old = self.peek(addr);
+            if let Some(watchpoints) = &self.watchpoints {
+                watchpoints.check(Access::Write, addr, old, val);
+            }
+        }
+        // The bus is driven by the DMA, so the write goes nowhere
+        if self.dma_conflict(addr).is_none() {
+            self.write_unwatched(addr, val);
+        }
+    }
+
+    // While OAM DMA runs, the CPU shares whichever bus it's reading from.
+    // Reads from anywhere on that bus see the byte being transferred.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        let source = self.dma.source()?;
+        // Everything else, including the WRAM read for sources past 0xdfff,
+        // is on the external bus
+        let vram_source = (0x8000..=0x9fff).contains(&source);
+        let conflict = match addr {
+            0x8000..=0x9fff => vram_source,
+            0x0000..=0x7fff | 0xa000..=0xfdff => !vram_source,
+            // OAM, IO registers and HRAM are on internal buses
+            _ => false,
+        };
+        conflict.then(|| self.dma_read(source))
+    }
+
+    // Reads without triggering watchpoints, for accesses that don't come from
+    // the program (OAM DMA, debugger)
+    pub fn peek(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x00ff if self.bootrom_enabled => {
-                if let Some(bootrom) = self.bootrom {
-                    bootrom[addr as usize]
-                } else {
-                    self.cartridge.read(addr)
+            // The CGB bootrom leaves a hole for the cartridge header, the DMG
+            // one ends there
+            0x0000..=0x00ff | 0x0200..=0x08ff
+                if self.bootrom_enabled && (addr < 0x100 || self.model.is_cgb()) =>
+            {
+                match &self.bootrom {
+                    Some(bootrom) => bootrom[addr as usize],
+                    None => self.cartridge.read(addr),
                 }
             }
             0x0000..=0x7fff => self.cartridge.read(addr),
             0x8000..=0x9fff => self.ppu.read(addr),
             0xa000..=0xbfff => self.cartridge.read(addr),
-            0xc000..=0xdfff => self.wram[addr as usize - 0xc000],
-            0xe000..=0xfdff => self.wram[addr as usize - 0xe000],
+            0xc000..=0xfdff => self.wram[self.wram_addr(addr)],
             0xfe00..=0xfe9f => {
                 if self.dma.slot.is_some() {
                     0xff
@@ -130,6 +316,7 @@ impl MemoryBus {
             0xff80..=0xfffe => self.hram[addr as usize - 0xff80],
 
             0xff00 => self.joypad.read(),
+            0xff01 | 0xff02 => self.serial.read(addr),
             0xff04 => {
                 let [_, msb] = self.timers.div.to_le_bytes();
                 msb
@@ -147,14 +334,17 @@ impl MemoryBus {
             0xff46 => self.dma.base,
             0xff50 => 0xff,
 
+            0xff4f | 0xff68..=0xff6c if self.model.is_cgb() => self.ppu.read(addr),
+            0xff4d if self.model.is_cgb() => {
+                ((self.double_speed as u8) << 7) | 0x7e | self.speed_switch as u8
+            }
+            0xff55 if self.model.is_cgb() => self.hdma.read(),
+            0xff70 if self.model.is_cgb() => self.wram_bank | 0xf8,
+
             0xff0f => self.int_flag | 0xe0,
             0xffff => self.int_enable,
 
-            // stubs
-            0xff01 => 0x00,
-            0xff02 => 0x7e,
-
-            // unused on DMG
+            // unused, or CGB-only registers on DMG
             0xff03
             | 0xff08..=0xff0e
             | 0xff15
@@ -165,13 +355,12 @@ impl MemoryBus {
         }
     }
 
-    pub fn write(&mut self, addr: u16, val: u8) {
+    fn write_unwatched(&mut self, addr: u16, val: u8) {
         match addr {
             0x0000..=0x7fff => self.cartridge.write(addr, val),
             0x8000..=0x9fff => self.ppu.write(addr, val),
             0xa000..=0xbfff => self.cartridge.write(addr, val),
-            0xc000..=0xdfff => self.wram[addr as usize - 0xc000] = val,
-            0xe000..=0xfdff => self.wram[addr as usize - 0xe000] = val,
+            0xc000..=0xfdff => self.wram[self.wram_addr(addr)] = val,
             0xfe00..=0xfe9f => {
                 if self.dma.slot.is_none() {
                     let [_, slot] = addr.to_be_bytes();
@@ -182,7 +371,8 @@ impl MemoryBus {
             0xff80..=0xfffe => self.hram[addr as usize - 0xff80] = val,
 
             0xff00 => self.joypad.write(val),
-            0xff04 => self.timers.div = 0,
+            0xff01 | 0xff02 => self.serial.write(addr, val),
+            0xff04 => self.timers.reset_div(),
             0xff05 => {
                 // TIMA writes are ignored on this M-cycle
                 if !self.timers.reload {
@@ -208,20 +398,19 @@ impl MemoryBus {
 
             0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.write(addr, val),
 
-            0xff46 => {
-                self.dma.base = val;
-                self.dma.enabled = true;
-            }
+            0xff46 => self.dma.start(val),
             0xff50 => {
                 if self.bootrom_enabled && val & 1 == 1 {
                     self.bootrom_enabled = false;
                 }
             }
 
-            // stubs
-            0xff01 | 0xff02 => {}
+            0xff4f | 0xff68..=0xff6c if self.model.is_cgb() => self.ppu.write(addr, val),
+            0xff4d if self.model.is_cgb() => self.speed_switch = val.bit(0),
+            0xff51..=0xff55 if self.model.is_cgb() => self.hdma.write(addr, val),
+            0xff70 if self.model.is_cgb() => self.wram_bank = val & 0b111,
 
-            // unused on DMG
+            // unused, or CGB-only registers on DMG
             0xff03
             | 0xff08..=0xff0e
             | 0xff15
@@ -232,45 +421,334 @@ impl MemoryBus {
         }
     }
 
-    pub fn tick_dma(&mut self) {
+    fn request_interrupt(&mut self, int: Interrupt) {
+        self.int_flag |= 1 << (int as u8);
+    }
+
+    // With the system clock stopped only the joypad can wake things up. The
+    // PPU and APU keep producing frames and silence so the frontend doesn't
+    // stall, with the screen holding its last image.
+    fn tick_stopped(&mut self) {
+        if self.speed_switch_delay > 0 {
+            self.speed_switch_delay -= 1;
+        } else if self.joypad.poll() {
+            self.request_interrupt(Interrupt::Joypad);
+            self.stop_mode = false;
+        }
+        if !self.odd_cycle {
+            self.ppu.step_stopped();
+            self.apu.tick_silent();
+        }
+        self.serial.tick_stopped(self.double_speed);
+        self.odd_cycle = self.double_speed && !self.odd_cycle;
+    }
+
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0xdfff => self.peek(addr),
+            0xe000..=0xffff => self.wram[self.wram_addr(addr & 0xdfff)],
+        }
+    }
+
+    fn tick_dma(&mut self) {
         if let Some((slot, addr)) = self.dma.tick() {
-            let val = match addr {
-                0x0000..=0xdfff => self.read(addr),
-                0xe000..=0xffff => self.wram[addr as usize - 0xe000],
-            };
+            let val = self.dma_read(addr);
             self.ppu.write_dma(slot, val);
         }
     }
 
+    // Index into WRAM, including echo RAM. 0xd000-0xdfff is switchable on CGB,
+    // where selecting bank 0 selects bank 1.
+    fn wram_addr(&self, addr: u16) -> usize {
+        let offset = (addr as usize - 0xc000) & 0x1fff;
+        if offset < 0x1000 {
+            offset
+        } else {
+            let bank = if self.model.is_cgb() {
+                self.wram_bank.max(1)
+            } else {
+                1
+            };
+            0x1000 * bank as usize + offset - 0x1000
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.watchpoints.as_ref().map_or(&[], |w| &w.list)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints
+            .get_or_insert_default()
+            .list
+            .push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        let watchpoints = self.watchpoints.as_mut()?;
+        let watchpoint = (index < watchpoints.list.len()).then(|| watchpoints.list.remove(index));
+        if watchpoints.list.is_empty() {
+            self.watchpoints = None;
+        }
+        watchpoint
+    }
+
+    // Called before each instruction so that hits can be attributed to it
+    fn set_watch_pc(&mut self, pc: u16) {
+        if let Some(watchpoints) = &mut self.watchpoints {
+            let bank = (pc < 0x8000).then(|| self.cartridge.rom_bank(pc));
+            watchpoints.set_pc(pc, bank);
+        }
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watchpoints
+            .as_mut()
+            .map_or_else(Vec::new, |w| w.take_hits())
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
     pub fn ppu_mut(&mut self) -> &mut Ppu {
         &mut self.ppu
     }
 }
 
+impl Bus for MemoryBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.oam_bug.read |= is_oam_bug_address(addr);
+        MemoryBus::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.oam_bug.write |= is_oam_bug_address(addr);
+        MemoryBus::write(self, addr, val)
+    }
+
+    fn tick(&mut self) {
+        if self.stopped() {
+            self.tick_stopped();
+            return;
+        }
+        if let Some(corruption) = std::mem::take(&mut self.oam_bug).corruption() {
+            self.ppu.corrupt_oam(corruption);
+        }
+        self.tick_dma();
+        if self.timers.increment(&mut self.apu, self.double_speed) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if self.serial.tick(self.timers.div, self.double_speed) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        if self.joypad.poll() {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+        // In double speed mode, the PPU, APU and RTC keep running at the
+        // normal rate, so they only step every other M-cycle.
+        if !self.odd_cycle {
+            self.cartridge.increment_rtc();
+            let (vblank, stat) = self.ppu.step();
+            self.apu.tick();
+            if self.ppu.hblank_check() {
+                self.hdma.hblank();
+            }
+            if vblank {
+                self.request_interrupt(Interrupt::VBlank);
+            }
+            if stat {
+                self.request_interrupt(Interrupt::Stat);
+            }
+        }
+        self.odd_cycle = self.double_speed && !self.odd_cycle;
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.int_flag & self.int_enable & 0x1f
+    }
+
+    fn acknowledge_interrupt(&mut self, int: u8) {
+        self.int_flag &= !(1 << int);
+    }
+
+    fn begin_instruction(&mut self, pc: u16) {
+        self.set_watch_pc(pc);
+        // The CPU is stalled while HDMA/GDMA copies blocks into VRAM, which
+        // takes 8 M-cycles per block (16 in double speed mode).
+        while let Some((source, dest)) = self.hdma.next_block() {
+            for i in 0..0x10 {
+                let val = MemoryBus::read(self, source.wrapping_add(i));
+                MemoryBus::write(self, dest + i, val);
+            }
+            let mcycles = if self.double_speed { 16 } else { 8 };
+            for _ in 0..mcycles {
+                self.tick();
+            }
+        }
+    }
+
+    fn increment_address(&mut self, addr: u16) {
+        self.oam_bug.increment |= is_oam_bug_address(addr);
+    }
+
+    fn stop(&mut self, ime: bool) -> Stop {
+        let interrupt_pending = self.pending_interrupts() != 0;
+        // STOP waits for a joypad line to go low, which can't happen if one
+        // already is
+        if self.joypad.line_low() {
+            return if interrupt_pending {
+                Stop::Continue
+            } else {
+                Stop::Halt
+            };
+        }
+        self.timers.reset_div();
+        // CGB speed switch, armed through KEY1
+        if self.speed_switch {
+            if interrupt_pending && ime {
+                return Stop::Hang;
+            }
+            self.speed_switch = false;
+            self.double_speed = !self.double_speed;
+            self.odd_cycle = false;
+            self.speed_switch_delay = SPEED_SWITCH_MCYCLES;
+        } else {
+            self.stop_mode = true;
+        }
+        Stop::Continue
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop_mode || self.speed_switch_delay > 0
+    }
+}
+
+enum Interrupt {
+    VBlank = 0,
+    Stat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4,
+}
+
+// OAM DMA. A transfer starts one M-cycle after FF46 is written, then copies a
+// byte per M-cycle. Writing FF46 again restarts it, with the old transfer
+// carrying on until the new one starts.
 #[derive(Default)]
 struct Dma {
+    // Last value written to FF46
     base: u8,
-    enabled: bool,
+    requested: bool,
+    // Source page and next OAM slot of the transfer in progress
+    source: u8,
     slot: Option<u8>,
 }
 
+impl_savestate!(Dma {
+    base,
+    requested,
+    source,
+    slot
+});
+
 impl Dma {
+    fn start(&mut self, base: u8) {
+        self.base = base;
+        self.requested = true;
+    }
+
+    // Address the transfer reads from on this M-cycle
+    fn source(&self) -> Option<u16> {
+        self.slot
+            .map(|slot| u16::from_be_bytes([self.source, slot]))
+    }
+
     fn tick(&mut self) -> Option<(u8, u16)> {
-        if self.enabled {
-            match self.slot {
-                Some(slot) => {
-                    let addr = u16::from_be_bytes([self.base, slot]);
-                    if slot == 0x9f {
-                        self.enabled = false;
-                        self.slot = None;
-                    } else {
-                        self.slot = Some(slot + 1);
-                    }
-                    return Some((slot, addr));
+        let transfer = self.slot.zip(self.source());
+        if let Some(slot) = self.slot {
+            self.slot = (slot < 0x9f).then_some(slot + 1);
+        }
+        if std::mem::take(&mut self.requested) {
+            self.source = self.base;
+            self.slot = Some(0);
+        }
+        transfer
+    }
+}
+
+// CGB VRAM DMA. A general purpose transfer (GDMA) copies everything at once,
+// an HBlank transfer copies one 16-byte block at the start of each HBlank.
+#[derive(Default)]
+struct Hdma {
+    source: u16,
+    dest: u16,
+    // Remaining blocks minus one, as read back from HDMA5
+    length: u8,
+    active: bool,
+    hblank_mode: bool,
+    pending: bool,
+}
+
+impl_savestate!(Hdma {
+    source,
+    dest,
+    length,
+    active,
+    hblank_mode,
+    pending,
+});
+
+impl Hdma {
+    fn read(&self) -> u8 {
+        ((!self.active as u8) << 7) | self.length
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff51 => self.source = (self.source & 0x00ff) | (val as u16) << 8,
+            0xff52 => self.source = (self.source & 0xff00) | (val & 0xf0) as u16,
+            0xff53 => self.dest = (self.dest & 0x00ff) | ((val & 0x1f) as u16) << 8,
+            0xff54 => self.dest = (self.dest & 0xff00) | (val & 0xf0) as u16,
+            0xff55 => {
+                if self.active && self.hblank_mode && !val.bit(7) {
+                    // Cancel the ongoing HBlank transfer
+                    self.active = false;
+                } else {
+                    self.length = val & 0x7f;
+                    self.active = true;
+                    self.hblank_mode = val.bit(7);
+                    self.pending = !self.hblank_mode;
                 }
-                None => self.slot = Some(0),
             }
+            _ => unreachable!(),
+        }
+    }
+
+    fn hblank(&mut self) {
+        if self.active && self.hblank_mode {
+            self.pending = true;
+        }
+    }
+
+    fn next_block(&mut self) -> Option<(u16, u16)> {
+        if !(self.active && self.pending) {
+            return None;
+        }
+        let block = (self.source, 0x8000 | self.dest);
+        self.source = self.source.wrapping_add(0x10);
+        self.dest = (self.dest + 0x10) & 0x1ff0;
+        self.length = self.length.wrapping_sub(1) & 0x7f;
+        if self.length == 0x7f {
+            self.active = false;
+        }
+        if self.hblank_mode {
+            self.pending = false;
         }
-        None
+        Some(block)
     }
 }
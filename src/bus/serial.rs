@@ -0,0 +1,156 @@
+use crate::model::Model;
+use crate::savestate::impl_savestate;
+use crate::utils::BitExtract;
+
+// Something plugged into the link port. Transfers are exchanged a byte at a
+// time, and the shift register then clocks the received byte in bit by bit.
+pub trait SerialDevice {
+    // The Game Boy started a transfer on its internal clock, shifting out
+    // `byte`. Returns the byte shifted back in, 0xff if nothing responds, or
+    // None if the reply isn't known yet and comes from `poll_reply` instead.
+    fn transfer(&mut self, byte: u8) -> Option<u8>;
+
+    // Polled every mcycle while the reply to `transfer` is outstanding. Bits
+    // keep shifting out meanwhile, but the transfer can't finish without it.
+    fn poll_reply(&mut self) -> Option<u8> {
+        Some(0xff)
+    }
+
+    // Polled every mcycle while the Game Boy waits for the peer to clock a
+    // transfer, with the byte it would shift out. Returns the received byte
+    // once the peer has clocked one.
+    fn poll_external(&mut self, byte: u8) -> Option<u8>;
+
+    // Called every mcycle before any transfer handling, for devices that
+    // keep time with the Game Boy. `passive` is whether it's waiting for the
+    // peer to clock a transfer. An mcycle is half as long in double speed.
+    fn tick(&mut self, _passive: bool, _double_speed: bool) {}
+}
+
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _: u8) -> Option<u8> {
+        Some(0xff)
+    }
+
+    fn poll_external(&mut self, _: u8) -> Option<u8> {
+        None
+    }
+}
+
+pub struct Serial {
+    model: Model,
+    sb: u8,
+    sc: u8,
+    incoming: u8,
+    bits: u8,
+    clock: bool,
+    awaiting_reply: bool,
+    device: Box<dyn SerialDevice>,
+}
+
+impl_savestate!(Serial {
+    sb,
+    sc,
+    incoming,
+    bits,
+    clock,
+});
+
+impl Serial {
+    pub fn new(model: Model) -> Self {
+        Self {
+            model,
+            sb: 0,
+            sc: 0,
+            incoming: 0xff,
+            bits: 0,
+            clock: false,
+            awaiting_reply: false,
+            device: Box::new(Disconnected),
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff01 => self.sb,
+            0xff02 if self.model.is_cgb() => self.sc | 0x7c,
+            0xff02 => self.sc | 0x7e,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff01 => self.sb = val,
+            0xff02 => {
+                // Bit 1 selects the fast clock, which only the CGB has
+                self.sc = val & if self.model.is_cgb() { 0x83 } else { 0x81 };
+                if self.sc.bit(7) && self.sc.bit(0) {
+                    let reply = self.device.transfer(self.sb);
+                    self.awaiting_reply = reply.is_none();
+                    self.incoming = reply.unwrap_or(0xff);
+                    self.bits = 8;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns whether a transfer completed this mcycle
+    pub fn tick(&mut self, div: u16, double_speed: bool) -> bool {
+        // The internal clock runs at 8192Hz, shifting one bit on every falling
+        // edge of bit 8 of the system counter, or at 262144Hz off bit 3 with
+        // the fast clock. The system counter runs twice as fast in double
+        // speed mode, and so do both clocks.
+        let clock = div.bit(if self.sc.bit(1) { 3 } else { 8 });
+        let falling_edge = self.clock && !clock;
+        self.clock = clock;
+
+        self.device.tick(self.passive(), double_speed);
+        if !self.sc.bit(7) {
+            return false;
+        }
+        if self.sc.bit(0) {
+            if self.awaiting_reply
+                && let Some(reply) = self.device.poll_reply()
+            {
+                // Ones were shifted in while waiting, put the real bits there
+                let shifted = 8 - self.bits;
+                let mask = ((1u16 << shifted) - 1) as u8;
+                self.sb = (self.sb & !mask) | (u16::from(reply) >> self.bits) as u8;
+                self.incoming = (u16::from(reply) << shifted) as u8;
+                self.awaiting_reply = false;
+            }
+            if falling_edge && self.bits > 0 {
+                self.sb = (self.sb << 1) | (self.incoming >> 7);
+                self.incoming <<= 1;
+                self.bits -= 1;
+            }
+            if self.bits == 0 && !self.awaiting_reply {
+                self.sc &= 0x7f;
+                return true;
+            }
+        } else if let Some(byte) = self.device.poll_external(self.sb) {
+            self.sb = byte;
+            self.sc &= 0x7f;
+            return true;
+        }
+        false
+    }
+
+    // Nothing shifts with the system clock stopped, but time still passes for
+    // the device
+    pub fn tick_stopped(&mut self, double_speed: bool) {
+        self.device.tick(self.passive(), double_speed);
+    }
+
+    fn passive(&self) -> bool {
+        self.sc.bit(7) && !self.sc.bit(0)
+    }
+}
@@ -0,0 +1,93 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchCondition {
+    Any,
+    // The accessed value, masked, equals `value`
+    Value { mask: u8, value: u8 },
+    // Only writes that change the stored value
+    Changed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+    pub condition: WatchCondition,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: Access, addr: u16, old: u8, new: u8) -> bool {
+        let kind_matches = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind_matches
+            && self.range.contains(&addr)
+            && match self.condition {
+                WatchCondition::Any => true,
+                WatchCondition::Value { mask, value } => new & mask == value,
+                WatchCondition::Changed => access == Access::Write && old != new,
+            }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct WatchHit {
+    pub access: Access,
+    pub addr: u16,
+    // PC of the instruction that made the access, and the ROM bank mapped
+    // there if it's in ROM
+    pub pc: u16,
+    pub bank: Option<usize>,
+    // For reads both are the value read
+    pub old: u8,
+    pub new: u8,
+}
+
+// The bus only holds one of these while watchpoints are set, so that memory
+// accesses don't pay for the checks otherwise.
+#[derive(Default)]
+pub struct Watchpoints {
+    pub list: Vec<Watchpoint>,
+    pc: u16,
+    bank: Option<usize>,
+    // Reads go through `&self`
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn set_pc(&mut self, pc: u16, bank: Option<usize>) {
+        self.pc = pc;
+        self.bank = bank;
+    }
+
+    pub fn check(&self, access: Access, addr: u16, old: u8, new: u8) {
+        if self
+            .list
+            .iter()
+            .any(|watchpoint| watchpoint.matches(access, addr, old, new))
+        {
+            self.hits.borrow_mut().push(WatchHit {
+                access,
+                addr,
+                pc: self.pc,
+                bank: self.bank,
+                old,
+                new,
+            });
+        }
+    }
+
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(self.hits.get_mut())
+    }
+}
@@ -1,64 +1,78 @@
-use anyhow::Result;
+use anyhow::{Result, bail, ensure};
 use num_traits::FromPrimitive;
 use std::fmt;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 
 mod instruction;
 mod registers;
 
 use crate::apu::Apu;
 use crate::bus::joypad::Joypad;
-use crate::bus::{Cartridge, MemoryBus};
+use crate::bus::serial::Serial;
+use crate::bus::{Bus, Cartridge, MemoryBus, Stop};
+use crate::model::Model;
 use crate::ppu::Ppu;
+use crate::savestate::{self, Savestate, impl_savestate, take};
 use crate::utils::BitExtract;
 use instruction::*;
+pub use registers::CpuRegisters;
 use registers::{Reg8, Reg16, RegWrite, Registers};
 
-pub struct Cpu {
+pub struct Cpu<B = MemoryBus> {
     registers: Registers,
-    memory: MemoryBus,
+    memory: B,
     cycles: u64,
     ime: bool,
+    // EI enables interrupts only after the next instruction
+    ei_pending: bool,
     halted: bool,
+    // HALT with IME=0 and an interrupt already pending doesn't halt, and the
+    // next opcode fetch doesn't increment PC
+    halt_bug: bool,
+    // After an illegal opcode or a STOP glitch the CPU stops executing for
+    // good
+    locked: bool,
+    breakpoint: bool,
     logfile: Option<BufWriter<Box<dyn Write>>>,
 }
 
-impl fmt::Debug for Cpu {
+impl_savestate!(Cpu {
+    registers,
+    memory,
+    cycles,
+    ime,
+    ei_pending,
+    halted,
+    halt_bug,
+    locked,
+});
+
+impl<B> fmt::Debug for Cpu<B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} | {:?} ", self.cycles, self.registers)
     }
 }
 
-enum Interrupt {
-    VBlank = 0,
-    Stat = 1,
-    Timer = 2,
-    #[allow(unused)]
-    Serial = 3,
-    Joypad = 4,
-}
-
 impl Cpu {
-    pub fn new(
-        bootrom: Option<[u8; 0x100]>,
-        cartridge: Cartridge,
-        apu: Apu,
-        logfile: Option<Box<dyn Write>>,
-    ) -> Self {
-        let mut cpu = Self {
-            memory: MemoryBus::new(bootrom, cartridge, apu),
-            registers: Registers::default(),
-            cycles: 0,
-            ime: false,
-            halted: false,
-            logfile: logfile.map(BufWriter::new),
-        };
+    pub fn new(model: Model, bootrom: Option<Vec<u8>>, cartridge: Cartridge, apu: Apu) -> Self {
+        let skip_bootrom = bootrom.is_none();
+        let mut cpu = Self::with_bus(MemoryBus::new(model, bootrom, cartridge, apu));
 
-        if bootrom.is_none() {
-            cpu.registers.write(RegWrite::AF(0x01b0));
-            cpu.registers.write(RegWrite::BC(0x0013));
-            cpu.registers.write(RegWrite::DE(0x00d8));
-            cpu.registers.write(RegWrite::HL(0x014d));
+        if skip_bootrom {
+            match model {
+                Model::Dmg => {
+                    cpu.registers.write(RegWrite::AF(0x01b0));
+                    cpu.registers.write(RegWrite::BC(0x0013));
+                    cpu.registers.write(RegWrite::DE(0x00d8));
+                    cpu.registers.write(RegWrite::HL(0x014d));
+                }
+                Model::Cgb => {
+                    cpu.registers.write(RegWrite::AF(0x1180));
+                    cpu.registers.write(RegWrite::BC(0x0000));
+                    cpu.registers.write(RegWrite::DE(0xff56));
+                    cpu.registers.write(RegWrite::HL(0x000d));
+                }
+            }
             cpu.registers.write(RegWrite::SP(0xfffe));
             cpu.registers.pc = 0x100;
             cpu.memory.write(0xff10, 0x80);
@@ -85,8 +99,68 @@ impl Cpu {
         cpu
     }
 
-    pub fn save_external_ram(&self) -> Result<()> {
-        self.memory.cartridge.save_external_ram()
+    pub fn save_external_ram(&mut self, writer: &mut dyn Write) -> Result<()> {
+        self.memory.cartridge.save_external_ram(writer)
+    }
+
+    pub fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.memory.cartridge.load_external_ram(reader)
+    }
+
+    pub fn savestate(&self) -> Vec<u8> {
+        let mut state = savestate::MAGIC.to_vec();
+        savestate::VERSION.save_state(&mut state);
+        self.memory
+            .cartridge
+            .global_checksum()
+            .save_state(&mut state);
+        self.save_state(&mut state);
+        state
+    }
+
+    pub fn load_savestate(&mut self, state: &[u8]) -> Result<()> {
+        let state = &mut self.savestate_body(state)?;
+        // Loading happens in place, so keep a copy of the current state to
+        // roll back to if the savestate turns out to be malformed.
+        let mut backup = Vec::new();
+        self.save_state(&mut backup);
+        let result = self.load_state(state).and_then(|()| {
+            if state.is_empty() {
+                Ok(())
+            } else {
+                bail!("Savestate has {} trailing bytes", state.len())
+            }
+        });
+        if result.is_err() {
+            self.load_state(&mut backup.as_slice())?;
+        }
+        result
+    }
+
+    // Loads a savestate made by `savestate` in this session, which can't be
+    // malformed, without the backup copy `load_savestate` makes
+    pub fn load_snapshot(&mut self, state: &[u8]) -> Result<()> {
+        let state = &mut self.savestate_body(state)?;
+        self.load_state(state)
+    }
+
+    // Checks the header, returning the rest of the savestate
+    fn savestate_body<'a>(&self, mut state: &'a [u8]) -> Result<&'a [u8]> {
+        if take(&mut state).ok().as_ref() != Some(savestate::MAGIC) {
+            bail!("Not a savestate file");
+        }
+        let version = u16::from_le_bytes(take(&mut state)?);
+        if version != savestate::VERSION {
+            bail!(
+                "Unsupported savestate version {version} (expected {})",
+                savestate::VERSION
+            );
+        }
+        let checksum = u16::from_le_bytes(take(&mut state)?);
+        if checksum != self.memory.cartridge.global_checksum() {
+            bail!("Savestate was created with a different ROM");
+        }
+        Ok(state)
     }
 
     pub fn run_frame(&mut self) -> Result<()> {
@@ -98,38 +172,135 @@ impl Cpu {
         }
     }
 
+    pub fn model(&self) -> Model {
+        self.memory.model()
+    }
+
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.memory.peek(addr)
+    }
+
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.memory.cartridge.rom_bank(addr)
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        self.memory.ppu()
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        self.memory.ppu_mut()
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.memory.apu
+    }
+
+    pub fn joypad_mut(&mut self) -> &mut Joypad {
+        &mut self.memory.joypad
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.memory.serial
+    }
+
+    // Disassembles the instruction at `addr` without side effects, returning
+    // its text and length
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        let mut next = addr;
+        let instr = Self::decode(|| {
+            let byte = self.memory.peek(next);
+            next = next.wrapping_add(1);
+            byte
+        });
+        match instr {
+            Ok(instr) => (format!("{instr:?}"), instr.length() as u16),
+            Err(byte) => (format!("DB ${byte:02x}"), 1),
+        }
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn with_bus(memory: B) -> Self {
+        Self {
+            memory,
+            registers: Registers::default(),
+            cycles: 0,
+            ime: false,
+            ei_pending: false,
+            halted: false,
+            halt_bug: false,
+            locked: false,
+            breakpoint: false,
+            logfile: None,
+        }
+    }
+
+    pub fn set_logfile(&mut self, logfile: Box<dyn Write>) {
+        self.logfile = Some(BufWriter::new(logfile));
+    }
+
     pub fn step(&mut self) -> Result<()> {
+        if self.locked || self.memory.stopped() {
+            self.mtick();
+            return Ok(());
+        }
+        self.memory.begin_instruction(self.registers.pc);
         self.check_for_interrupts();
+        // Takes effect before the instruction executes, so that EI followed
+        // by DI never enables interrupts
+        if std::mem::take(&mut self.ei_pending) {
+            self.ime = true;
+        }
         if self.halted {
             self.mtick();
         } else {
             let cycles = self.cycles;
             let pc = self.registers.pc;
-            let state = self
-                .logfile
-                .is_some()
-                .then(|| format!("{self:?}"))
-                .unwrap_or_default();
-            let instr = self.decode_instr();
+            let halt_bug = self.halt_bug;
+            let state = if self.logfile.is_some() {
+                format!("{self:?}")
+            } else {
+                String::new()
+            };
+            let instr = match Self::decode(|| self.u8_arg()) {
+                Ok(instr) => instr,
+                Err(byte) => {
+                    log::warn!("Illegal opcode {byte:02x} at {pc:04x}, the CPU has locked up");
+                    self.locked = true;
+                    return Ok(());
+                }
+            };
             if let Some(logfile) = self.logfile.as_mut() {
                 writeln!(logfile, "{state} {instr:?}")?;
             }
-            assert_eq!(instr.length() as u16, self.registers.pc - pc);
+            ensure!(
+                halt_bug || instr.length() as u16 == self.registers.pc.wrapping_sub(pc),
+                "{instr:?} at {pc:04x} decoded with the wrong length"
+            );
+            // LD B,B is the conventional software breakpoint used by test ROMs
+            if matches!(instr, Instruction::Ld(LdType::R8(R8::B, R8::B))) {
+                self.breakpoint = true;
+            }
             let instr_cycles = self.execute_instr(instr);
-            assert_eq!(instr_cycles, self.cycles - cycles);
+            ensure!(
+                instr_cycles == self.cycles - cycles,
+                "{instr:?} at {pc:04x} took {} M-cycles instead of {instr_cycles}",
+                self.cycles - cycles
+            );
         }
         Ok(())
     }
 
     fn check_for_interrupts(&mut self) {
-        let int = self.memory.int_flag & self.memory.int_enable;
+        let int = self.memory.pending_interrupts();
         for i in 0..5 {
             if int.bit(i) {
                 self.halted = false;
                 if self.ime {
                     self.mtick();
                     self.mtick();
-                    self.memory.int_flag &= !(1 << i);
+                    self.memory.acknowledge_interrupt(i);
                     self.ime = false;
                     self.push16(self.registers.pc);
                     self.registers.pc = 0x40 + (i << 3) as u16;
@@ -141,44 +312,50 @@ impl Cpu {
     }
 
     fn mtick(&mut self) {
-        self.memory.tick_dma();
-        if self.memory.timers.increment(&mut self.memory.apu) {
-            self.request_interrupt(Interrupt::Timer);
-        }
-        self.memory.cartridge.increment_rtc();
-        if self.memory.joypad.poll() {
-            self.request_interrupt(Interrupt::Joypad);
-        }
-        let (vblank, stat) = self.ppu_mut().step();
-        self.memory.apu.tick();
-        if vblank {
-            self.request_interrupt(Interrupt::VBlank);
-        }
-        if stat {
-            self.request_interrupt(Interrupt::Stat);
-        }
+        self.memory.tick();
         self.cycles += 1;
     }
 
-    pub fn toggle_frame_limiter(&mut self) {
-        self.memory.apu.toggle_frame_limiter();
+    pub fn registers(&self) -> CpuRegisters {
+        self.registers.snapshot()
     }
 
-    fn request_interrupt(&mut self, int: Interrupt) {
-        self.memory
-            .write(0xff0f, self.memory.read(0xff0f) | 1 << (int as u8));
+    pub fn set_registers(&mut self, registers: CpuRegisters) {
+        self.registers.restore(registers);
     }
 
-    pub fn ppu_mut(&mut self) -> &mut Ppu {
-        self.memory.ppu_mut()
+    pub fn ime(&self) -> bool {
+        self.ime
     }
 
-    pub fn joypad_mut(&mut self) -> &mut Joypad {
-        &mut self.memory.joypad
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+    }
+
+    pub fn memory(&self) -> &B {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut B {
+        &mut self.memory
+    }
+
+    pub fn locked_up(&self) -> bool {
+        self.locked
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint)
     }
 
-    fn decode_instr(&mut self) -> Instruction {
-        let byte = self.u8_arg();
+    // Decodes the instruction made up of the bytes returned by `next_byte`.
+    // Opcodes that don't exist are returned as the error.
+    fn decode(mut next_byte: impl FnMut() -> u8) -> Result<Instruction, u8> {
+        let byte = next_byte();
         let lo_3bit = byte & 0b111;
         let hi_3bit = (byte & 0b111111) >> 3;
         let hi_2bit = hi_3bit >> 1;
@@ -188,34 +365,44 @@ impl Cpu {
         let ind = Indirect::from_u8(hi_2bit).unwrap();
         let branch = BranchCond::from_u8(hi_3bit & 0b11).unwrap();
         let push_pop = PushPop::from_u8(hi_2bit).unwrap();
-        match byte {
+        let instr = match byte {
             0x00 => Instruction::Nop,
-            0x01 | 0x11 | 0x21 | 0x31 => Instruction::Ld(LdType::R16Imm(r16, self.u16_arg())),
+            0x01 | 0x11 | 0x21 | 0x31 => Instruction::Ld(LdType::R16Imm(
+                r16,
+                u16::from_le_bytes([next_byte(), next_byte()]),
+            )),
             0x02 | 0x12 | 0x22 | 0x32 => Instruction::Ld(LdType::IndFromA(ind)),
             0x03 | 0x13 | 0x23 | 0x33 => Instruction::IncR16(r16),
             0x04 | 0x14 | 0x24 | 0x34 | 0x0c | 0x1c | 0x2c | 0x3c => Instruction::IncR8(r8_hi),
             0x05 | 0x15 | 0x25 | 0x35 | 0x0d | 0x1d | 0x2d | 0x3d => Instruction::DecR8(r8_hi),
             0x06 | 0x16 | 0x26 | 0x36 | 0x0e | 0x1e | 0x2e | 0x3e => {
-                Instruction::Ld(LdType::R8Imm(r8_hi, self.u8_arg()))
+                Instruction::Ld(LdType::R8Imm(r8_hi, next_byte()))
             }
-            0x08 => Instruction::Ld(LdType::StoreSP(self.u16_arg())),
+            0x08 => Instruction::Ld(LdType::StoreSP(u16::from_le_bytes([
+                next_byte(),
+                next_byte(),
+            ]))),
             0x09 | 0x19 | 0x29 | 0x39 => Instruction::AddHL(r16),
             0x0a | 0x1a | 0x2a | 0x3a => Instruction::Ld(LdType::AFromInd(ind)),
             0x0b | 0x1b | 0x2b | 0x3b => Instruction::DecR16(r16),
-            0x20 | 0x28 | 0x30 | 0x38 => Instruction::Jr(branch, self.u8_arg() as i8),
+            0x20 | 0x28 | 0x30 | 0x38 => Instruction::Jr(branch, next_byte() as i8),
             0x40..=0x75 | 0x77..=0x7f => Instruction::Ld(LdType::R8(r8_hi, r8_lo)),
             0x80..=0xbf => Self::decode_alu_instr(byte, AluSrc::R8(r8_lo)),
             0xc0 | 0xd0 | 0xc8 | 0xd8 => Instruction::Ret(branch),
             0xc1 | 0xd1 | 0xe1 | 0xf1 => Instruction::Pop(push_pop),
-            0xc2 | 0xd2 | 0xca | 0xda => Instruction::Jp(branch, self.u16_arg()),
-            0xc4 | 0xd4 | 0xcc | 0xdc => Instruction::Call(branch, self.u16_arg()),
+            0xc2 | 0xd2 | 0xca | 0xda => {
+                Instruction::Jp(branch, u16::from_le_bytes([next_byte(), next_byte()]))
+            }
+            0xc4 | 0xd4 | 0xcc | 0xdc => {
+                Instruction::Call(branch, u16::from_le_bytes([next_byte(), next_byte()]))
+            }
             0xc5 | 0xd5 | 0xe5 | 0xf5 => Instruction::Push(push_pop),
             0xc6 | 0xd6 | 0xe6 | 0xf6 | 0xce | 0xde | 0xee | 0xfe => {
-                Self::decode_alu_instr(byte, AluSrc::Imm(self.u8_arg()))
+                Self::decode_alu_instr(byte, AluSrc::Imm(next_byte()))
             }
             0xc7 | 0xd7 | 0xe7 | 0xf7 | 0xcf | 0xdf | 0xef | 0xff => Instruction::Rst(hi_3bit << 3),
             0xcb => {
-                let second_byte = self.u8_arg();
+                let second_byte = next_byte();
                 let bit = BitPos::from_u8((second_byte & 0b111111) >> 3).unwrap();
                 let r8 = R8::from_u8(second_byte & 0b111).unwrap();
                 match second_byte {
@@ -232,39 +419,46 @@ impl Cpu {
                     0xc0..=0xff => Instruction::Set(bit, r8),
                 }
             }
-            0xe0 => Instruction::Ld(LdType::IoRegFromA(Io::Imm(self.u8_arg()))),
+            0xe0 => Instruction::Ld(LdType::IoRegFromA(Io::Imm(next_byte()))),
             0xe2 => Instruction::Ld(LdType::IoRegFromA(Io::C)),
-            0xea => Instruction::Ld(LdType::MemFromA(self.u16_arg())),
-            0xf0 => Instruction::Ld(LdType::AFromIoReg(Io::Imm(self.u8_arg()))),
+            0xea => Instruction::Ld(LdType::MemFromA(u16::from_le_bytes([
+                next_byte(),
+                next_byte(),
+            ]))),
+            0xf0 => Instruction::Ld(LdType::AFromIoReg(Io::Imm(next_byte()))),
             0xf2 => Instruction::Ld(LdType::AFromIoReg(Io::C)),
-            0xfa => Instruction::Ld(LdType::AFromMem(self.u16_arg())),
+            0xfa => Instruction::Ld(LdType::AFromMem(u16::from_le_bytes([
+                next_byte(),
+                next_byte(),
+            ]))),
 
             0x07 => Instruction::Rlca,
             0x0f => Instruction::Rrca,
             0x10 => Instruction::Stop,
             0x17 => Instruction::Rla,
-            0x18 => Instruction::JrAlways(self.u8_arg() as i8),
+            0x18 => Instruction::JrAlways(next_byte() as i8),
             0x1f => Instruction::Rra,
             0x27 => Instruction::Daa,
             0x2f => Instruction::Cpl,
             0x37 => Instruction::Scf,
             0x3f => Instruction::Ccf,
             0x76 => Instruction::Halt,
-            0xc3 => Instruction::JpAlways(self.u16_arg()),
+            0xc3 => Instruction::JpAlways(u16::from_le_bytes([next_byte(), next_byte()])),
             0xc9 => Instruction::RetAlways,
-            0xcd => Instruction::CallAlways(self.u16_arg()),
+            0xcd => Instruction::CallAlways(u16::from_le_bytes([next_byte(), next_byte()])),
             0xd9 => Instruction::Reti,
-            0xe8 => Instruction::AddSP(self.u8_arg() as i8),
+            0xe8 => Instruction::AddSP(next_byte() as i8),
             0xe9 => Instruction::JpHL,
             0xf3 => Instruction::Di,
             0xfb => Instruction::Ei,
-            0xf8 => Instruction::Ld(LdType::HLFromSP(self.u8_arg() as i8)),
+            0xf8 => Instruction::Ld(LdType::HLFromSP(next_byte() as i8)),
             0xf9 => Instruction::Ld(LdType::SPFromHL),
 
             0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
-                panic!("Crash opcode: {byte:02x}")
+                return Err(byte);
             }
-        }
+        };
+        Ok(instr)
     }
 
     fn execute_instr(&mut self, instr: Instruction) -> u64 {
@@ -306,7 +500,7 @@ impl Cpu {
                     let [lsb, msb] = self.read16(R16::SP).to_le_bytes();
                     self.memory.write(addr, lsb);
                     self.mtick();
-                    self.memory.write(addr + 1, msb);
+                    self.memory.write(addr.wrapping_add(1), msb);
                     self.mtick();
                 }
                 LdType::HLFromSP(offset) => {
@@ -333,13 +527,15 @@ impl Cpu {
                 self.set_flags(Some(val == 0), Some(true), Some(h), None);
             }
             Instruction::IncR16(r16) => {
-                let val = self.read16(r16).wrapping_add(1);
-                self.write16(r16, val);
+                let val = self.read16(r16);
+                self.memory.increment_address(val);
+                self.write16(r16, val.wrapping_add(1));
                 self.mtick();
             }
             Instruction::DecR16(r16) => {
-                let val = self.read16(r16).wrapping_sub(1);
-                self.write16(r16, val);
+                let val = self.read16(r16);
+                self.memory.increment_address(val);
+                self.write16(r16, val.wrapping_sub(1));
                 self.mtick();
             }
 
@@ -646,11 +842,25 @@ impl Cpu {
             }
 
             Instruction::Di => self.ime = false,
-            Instruction::Ei => self.ime = true,
+            Instruction::Ei => self.ei_pending = true,
 
-            Instruction::Halt => self.halted = true,
+            Instruction::Halt => {
+                if !self.ime && self.memory.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
             Instruction::Stop => {
-                // panic!("Unimplemented instruction: {:?}", instr)
+                // The byte after STOP is skipped unless an interrupt is pending
+                if self.memory.pending_interrupts() == 0 {
+                    self.registers.pc = self.registers.pc.wrapping_add(1);
+                }
+                match self.memory.stop(self.ime) {
+                    Stop::Continue => {}
+                    Stop::Halt => self.halted = true,
+                    Stop::Hang => self.locked = true,
+                }
             }
         }
 
@@ -683,14 +893,12 @@ impl Cpu {
     fn u8_arg(&mut self) -> u8 {
         let arg = self.memory.read(self.registers.pc);
         self.mtick();
-        self.registers.pc += 1;
+        if !std::mem::take(&mut self.halt_bug) {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
         arg
     }
 
-    fn u16_arg(&mut self) -> u16 {
-        u16::from_le_bytes([self.u8_arg(), self.u8_arg()])
-    }
-
     fn read8(&mut self, r8: R8) -> u8 {
         match r8 {
             R8::A => self.registers.reg8(Reg8::A),
@@ -748,12 +956,14 @@ impl Cpu {
             MemIndirect::HLInc => {
                 let hl = self.registers.reg16(Reg16::HL);
                 let val = self.memory.read(hl);
+                self.memory.increment_address(hl);
                 self.registers.write(RegWrite::HL(hl.wrapping_add(1)));
                 val
             }
             MemIndirect::HLDec => {
                 let hl = self.registers.reg16(Reg16::HL);
                 let val = self.memory.read(hl);
+                self.memory.increment_address(hl);
                 self.registers.write(RegWrite::HL(hl.wrapping_sub(1)));
                 val
             }
@@ -770,11 +980,13 @@ impl Cpu {
             MemIndirect::HLInc => {
                 let hl = self.registers.reg16(Reg16::HL);
                 self.memory.write(hl, val);
+                self.memory.increment_address(hl);
                 self.registers.write(RegWrite::HL(hl.wrapping_add(1)));
             }
             MemIndirect::HLDec => {
                 let hl = self.registers.reg16(Reg16::HL);
                 self.memory.write(hl, val);
+                self.memory.increment_address(hl);
                 self.registers.write(RegWrite::HL(hl.wrapping_sub(1)));
             }
         }
@@ -838,9 +1050,11 @@ impl Cpu {
 
     fn push8(&mut self, val: u8) {
         let sp = self.registers.reg16(Reg16::SP);
-        self.registers.write(RegWrite::SP(sp - 1));
+        self.memory.increment_address(sp);
+        let sp = sp.wrapping_sub(1);
+        self.registers.write(RegWrite::SP(sp));
         self.mtick();
-        self.memory.write(sp - 1, val);
+        self.memory.write(sp, val);
     }
 
     fn push16(&mut self, val: u16) {
@@ -852,7 +1066,8 @@ impl Cpu {
     fn pop8(&mut self) -> u8 {
         let sp = self.registers.reg16(Reg16::SP);
         let val = self.memory.read(sp);
-        self.registers.write(RegWrite::SP(sp + 1));
+        self.memory.increment_address(sp);
+        self.registers.write(RegWrite::SP(sp.wrapping_add(1)));
         self.mtick();
         val
     }
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::savestate::impl_savestate;
+
 #[derive(Default)]
 pub struct Registers {
     a: u8,
@@ -14,7 +16,51 @@ pub struct Registers {
     pub flags: Flags,
 }
 
+// Copy of the register file exposed outside the core, for debugging and test
+// harnesses
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CpuRegisters {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
 impl Registers {
+    pub fn snapshot(&self) -> CpuRegisters {
+        CpuRegisters {
+            a: self.a,
+            f: self.flags.into(),
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+
+    pub fn restore(&mut self, registers: CpuRegisters) {
+        self.a = registers.a;
+        self.flags = registers.f.into();
+        self.b = registers.b;
+        self.c = registers.c;
+        self.d = registers.d;
+        self.e = registers.e;
+        self.h = registers.h;
+        self.l = registers.l;
+        self.sp = registers.sp;
+        self.pc = registers.pc;
+    }
+
     pub fn reg8(&self, src: Reg8) -> u8 {
         match src {
             Reg8::A => self.a,
@@ -60,6 +106,19 @@ impl Registers {
     }
 }
 
+impl_savestate!(Registers {
+    a,
+    b,
+    c,
+    d,
+    e,
+    h,
+    l,
+    sp,
+    pc,
+    flags
+});
+
 impl fmt::Debug for Registers {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -95,6 +154,8 @@ impl From<Flags> for u8 {
     }
 }
 
+impl_savestate!(Flags { z, n, h, c });
+
 impl fmt::Debug for Flags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let z = if self.z { "Z" } else { "-" };
@@ -0,0 +1,239 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use winit::application::ApplicationHandler;
+use winit::event::WindowEvent;
+use winit::event_loop::ActiveEventLoop;
+use winit::window::WindowId;
+
+use super::Machine;
+use super::audio::Audio;
+use super::config::{Args, Config};
+use super::debugger::Debugger;
+use super::display::{Display, DisplayEvent};
+use super::hotkeys::Hotkey;
+use super::rewind::Rewind;
+use super::save::{SaveFile, write_atomic};
+
+const NUM_SLOTS: u8 = 10;
+
+pub struct App {
+    machine: Machine,
+    display: Display,
+    audio: Audio,
+    debugger: Debugger,
+    rewind: Rewind,
+    // One per Game Boy
+    save_files: Vec<SaveFile>,
+    save_paths: Vec<PathBuf>,
+    slot: u8,
+    // Emulation stops after a fault, until a savestate is loaded
+    faulted: bool,
+}
+
+impl App {
+    pub fn new(args: Args, config: Config) -> Result<Self> {
+        let mut machine = Machine::new(&args, &config)?;
+        let players = machine.gameboys().len();
+        let display = Display::new(config.keymap(players), config.scale, players);
+
+        std::fs::create_dir_all(&config.saves_dir)?;
+        let cartridges = [args.cartridge()]
+            .into_iter()
+            .chain(args.linked_cartridges().iter().map(PathBuf::as_path));
+        let save_paths = save_paths(&config.saves_dir, cartridges);
+        let mut save_files = Vec::new();
+        for (gb, path) in machine.gameboys_mut().iter_mut().zip(&save_paths) {
+            let save_file = SaveFile::new(path.clone(), config.autosave_interval);
+            save_file.load(gb)?;
+            save_files.push(save_file);
+        }
+
+        let audio = Audio::new(config.audio_volume, args.disable_audio);
+        let debugger = Debugger::new(args.debug_break);
+        // Rewinding one side of a link would desync it from the others
+        let rewind_buffer_mb = match machine {
+            Machine::Single(_) => config.rewind_buffer_mb,
+            Machine::Linked(_) | Machine::FourPlayer(_) => 0,
+        };
+        Ok(Self {
+            machine,
+            display,
+            audio,
+            debugger,
+            rewind: Rewind::new(rewind_buffer_mb),
+            save_files,
+            save_paths,
+            slot: 0,
+            faulted: false,
+        })
+    }
+
+    fn savestate_path(&self, player: usize) -> PathBuf {
+        self.save_paths[player].with_extension(format!("ss{}", self.slot))
+    }
+
+    // Linked Game Boys are saved and loaded together
+    fn save_state(&self) -> Result<()> {
+        for (i, gb) in self.machine.gameboys().iter().enumerate() {
+            write_atomic(&self.savestate_path(i), &gb.savestate())?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self) -> Result<()> {
+        let states = (0..self.save_paths.len())
+            .map(|i| std::fs::read(self.savestate_path(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (gb, state) in self.machine.gameboys_mut().iter_mut().zip(states) {
+            gb.load_savestate(&state)?;
+        }
+        if self.faulted {
+            self.faulted = false;
+            self.display.show_error(None);
+        }
+        Ok(())
+    }
+
+    // Keeps the window open on the last frame so the error can be seen, after
+    // making sure the game's save isn't lost
+    fn fault(&mut self, error: anyhow::Error) {
+        eprintln!("{error:?}");
+        self.save_all();
+        self.faulted = true;
+        self.display
+            .show_error(Some(&format!("{error:#}\n\nLoad a savestate to continue")));
+    }
+
+    fn save_all(&mut self) {
+        let gameboys = self.machine.gameboys_mut();
+        for (gb, save_file) in gameboys.iter_mut().zip(&mut self.save_files) {
+            if let Err(e) = save_file.save(gb) {
+                println!("Failed to save: {e:?}");
+            }
+        }
+    }
+}
+
+// Every player gets their game's usual save, unless someone before them is
+// playing the same game, in which case theirs gets their player number as a
+// suffix
+fn save_paths<'a>(saves_dir: &Path, cartridges: impl Iterator<Item = &'a Path>) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for (i, cartridge) in cartridges.enumerate() {
+        let mut stem = cartridge.file_stem().unwrap().to_os_string();
+        if paths.iter().any(|path| path.file_stem() == Some(&stem)) {
+            stem.push(format!("-{}", i + 1));
+        }
+        paths.push(saves_dir.join(stem).with_extension("sav"));
+    }
+    paths
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if let Err(e) = self.display.reinit_surface(event_loop) {
+            println!("{e:?}");
+            self.display.quit(event_loop);
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        if let Some(display_event) = self.display.process_event(&event) {
+            match display_event {
+                DisplayEvent::RedrawRequested if self.faulted => {
+                    // Only redraw the last frame
+                    let _ = self.display.draw_frame(&mut self.machine, |_| Ok(()));
+                }
+                DisplayEvent::RedrawRequested => {
+                    let run_frame = |machine: &mut Machine| {
+                        match machine {
+                            Machine::Single(gb) if self.rewind.is_rewinding() => {
+                                return self.rewind.step_back(gb);
+                            }
+                            Machine::Single(gb) => {
+                                self.debugger.run_frame(gb)?;
+                                self.rewind.push(gb);
+                            }
+                            Machine::Linked(linked) => linked.run_frame()?,
+                            Machine::FourPlayer(adapter) => adapter.run_frame()?,
+                        }
+                        // Only player 1 is heard
+                        let [player1, others @ ..] = machine.gameboys_mut() else {
+                            unreachable!()
+                        };
+                        self.audio.queue(player1.audio_samples());
+                        for gb in others {
+                            gb.audio_samples().for_each(drop);
+                        }
+                        let gameboys = machine.gameboys_mut();
+                        for (gb, save_file) in gameboys.iter_mut().zip(&mut self.save_files) {
+                            if let Err(e) = save_file.autosave(gb) {
+                                println!("Failed to save: {e:?}");
+                            }
+                        }
+                        Ok(())
+                    };
+                    if let Err(e) = self.display.draw_frame(&mut self.machine, run_frame) {
+                        self.fault(e);
+                    }
+                }
+                DisplayEvent::Hotkey((hotkey, pressed)) => match hotkey {
+                    Hotkey::Joypad(player, button) => {
+                        if let Some(gb) = self.machine.gameboys_mut().get_mut(player) {
+                            gb.update_button(button, pressed);
+                        }
+                    }
+                    Hotkey::ToggleFrameLimiter => {
+                        if pressed {
+                            self.display.toggle_frame_limiter();
+                            self.audio.toggle_frame_limiter();
+                        }
+                    }
+                    Hotkey::SaveState => {
+                        if pressed {
+                            match self.save_state() {
+                                Ok(()) => println!("Saved state to slot {}", self.slot),
+                                Err(e) => println!("Failed to save state: {e:?}"),
+                            }
+                        }
+                    }
+                    Hotkey::LoadState => {
+                        if pressed {
+                            match self.load_state() {
+                                Ok(()) => println!("Loaded state from slot {}", self.slot),
+                                Err(e) => println!("Failed to load state: {e:?}"),
+                            }
+                        }
+                    }
+                    Hotkey::NextSlot => {
+                        if pressed {
+                            self.slot = (self.slot + 1) % NUM_SLOTS;
+                            println!("Selected savestate slot {}", self.slot);
+                        }
+                    }
+                    Hotkey::PrevSlot => {
+                        if pressed {
+                            self.slot = (self.slot + NUM_SLOTS - 1) % NUM_SLOTS;
+                            println!("Selected savestate slot {}", self.slot);
+                        }
+                    }
+                    Hotkey::Rewind => self.rewind.set_rewinding(pressed),
+                    Hotkey::Debug => {
+                        if pressed {
+                            match self.machine {
+                                Machine::Single(_) => self.debugger.request_break(),
+                                Machine::Linked(_) | Machine::FourPlayer(_) => {
+                                    println!("The debugger isn't available for linked Game Boys")
+                                }
+                            }
+                        }
+                    }
+                },
+                DisplayEvent::Quit => {
+                    self.save_all();
+                    self.display.quit(event_loop);
+                }
+            }
+        }
+    }
+}
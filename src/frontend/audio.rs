@@ -0,0 +1,82 @@
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::Instant;
+
+use anyhow::Result;
+use cpal::StreamConfig;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rgb::SAMPLE_RATE;
+
+pub struct Audio {
+    sample_tx: Option<Sender<(f32, f32)>>,
+    limit_framerate: bool,
+    instant: Instant,
+}
+
+impl Audio {
+    pub fn new(volume: f32, disable_audio: bool) -> Self {
+        let sample_tx = (!disable_audio).then(|| {
+            let (sample_tx, sample_rx) = channel();
+            std::thread::spawn(move || spawn_audio(sample_rx, volume));
+            sample_tx
+        });
+        Self {
+            sample_tx,
+            limit_framerate: true,
+            instant: Instant::now(),
+        }
+    }
+
+    pub fn queue(&mut self, samples: impl Iterator<Item = (f32, f32)>) {
+        let Some(sample_tx) = &self.sample_tx else {
+            return;
+        };
+        // When running unthrottled, only forward as many samples as play back
+        // in real time, so the output doesn't fall further and further behind.
+        let budget = if self.limit_framerate {
+            usize::MAX
+        } else {
+            let elapsed = self.instant.elapsed().as_secs_f64();
+            (elapsed * SAMPLE_RATE as f64) as usize
+        };
+        if budget > 0 {
+            for sample in samples.take(budget) {
+                let _ = sample_tx.send(sample);
+            }
+            self.instant = Instant::now();
+        }
+    }
+
+    pub fn toggle_frame_limiter(&mut self) {
+        self.limit_framerate = !self.limit_framerate;
+    }
+}
+
+fn spawn_audio(sample_rx: Receiver<(f32, f32)>, volume: f32) -> Result<()> {
+    let host = cpal::default_host();
+    let device = host
+        .default_output_device()
+        .ok_or_else(|| anyhow::Error::msg("Default output device is not available"))?;
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: cpal::SampleRate(SAMPLE_RATE),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _| {
+            for frame in data.chunks_mut(2) {
+                if let Ok((left, right)) = sample_rx.recv() {
+                    frame[0] = left * volume / 100.0;
+                    frame[1] = right * volume / 100.0;
+                }
+            }
+        },
+        |err| eprintln!("{err}"),
+        None,
+    )?;
+    stream.play()?;
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "frontend")]
+use super::hotkeys::{KeyMap, Keybindings};
+
+use anyhow::Result;
+use clap::{ArgGroup, Parser, Subcommand};
+use serde::Deserialize;
+
+#[derive(Parser)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+#[command(group(ArgGroup::new("multiplayer").args(["link", "four_player"])))]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(id = "rom-path", hide = true, required = true)]
+    cartridge: Option<PathBuf>,
+
+    #[arg(id = "rom2-path", hide = true, num_args = 1..=3, requires = "multiplayer")]
+    other_cartridges: Vec<PathBuf>,
+
+    #[arg(long)]
+    pub skip_bootrom: bool,
+
+    #[arg(short, long, default_value = "config.toml", help = "Config file")]
+    pub config: PathBuf,
+
+    #[arg(short, long, help = "Enable debug logs")]
+    pub logfile: Option<PathBuf>,
+
+    #[arg(long, default_value = "false")]
+    pub disable_audio: bool,
+
+    #[arg(long, help = "Break into the debugger before the first instruction")]
+    pub debug_break: bool,
+
+    #[arg(long, help = "Run without a window or audio device")]
+    pub headless: bool,
+
+    #[arg(
+        long,
+        default_value_t = 600,
+        requires = "headless",
+        help = "Number of frames to run in headless mode"
+    )]
+    pub frames: u64,
+
+    #[arg(long, requires = "headless", help = "Save the final frame as a PNG")]
+    pub screenshot: Option<PathBuf>,
+
+    #[arg(long, requires = "headless", help = "Joypad input script")]
+    pub input: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_LINK_ADDR,
+        conflicts_with = "link_connect",
+        help = "Wait for a link cable connection (BGB protocol)"
+    )]
+    pub link_listen: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_LINK_ADDR,
+        help = "Connect the link cable to another emulator (BGB protocol)"
+    )]
+    pub link_connect: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["link_listen", "link_connect", "link", "four_player"],
+        help = "Attach a Game Boy Printer, saving prints to prints_dir"
+    )]
+    pub printer: bool,
+
+    #[arg(
+        long,
+        requires = "rom2-path",
+        conflicts_with_all = ["headless", "debug_break", "link_listen", "link_connect"],
+        help = "Link two Game Boys in one window: rgb --link <ROM1> <ROM2>"
+    )]
+    pub link: bool,
+
+    #[arg(
+        long,
+        requires = "rom2-path",
+        conflicts_with_all = ["headless", "debug_break", "link_listen", "link_connect"],
+        help = "Plug 2-4 Game Boys into a four player adapter (DMG-07)"
+    )]
+    pub four_player: bool,
+}
+
+// BGB's default port
+const DEFAULT_LINK_ADDR: &str = "127.0.0.1:8765";
+
+impl Args {
+    pub fn cartridge(&self) -> &Path {
+        // Only missing when running a subcommand
+        self.cartridge.as_deref().expect("ROM path is required")
+    }
+
+    // Players 2 to 4 with `--link` or `--four-player`
+    #[cfg(feature = "frontend")]
+    pub fn linked_cartridges(&self) -> &[PathBuf] {
+        &self.other_cartridges
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "Print the cartridge header of one or more ROMs")]
+    Info {
+        #[arg(required = true)]
+        roms: Vec<PathBuf>,
+    },
+}
+
+// Settings added after the first release have defaults, so older config files
+// still load. Builds without the window frontend ignore its settings.
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+pub struct Config {
+    pub bootrom: String,
+    #[serde(default = "default_cgb_bootrom")]
+    pub cgb_bootrom: String,
+    pub saves_dir: PathBuf,
+    // Where `--printer` saves each print job as a PNG
+    #[serde(default = "default_prints_dir")]
+    pub prints_dir: PathBuf,
+    // Seconds between saves while RAM has unsaved changes, 0 disables
+    #[serde(default = "default_autosave_interval")]
+    pub autosave_interval: u64,
+    #[serde(rename = "volume")]
+    pub audio_volume: f32,
+    pub scale: u32,
+    // Memory for rewind snapshots, 0 disables rewinding
+    #[serde(default = "default_rewind_buffer_mb")]
+    pub rewind_buffer_mb: usize,
+    #[cfg(feature = "frontend")]
+    #[serde(rename = "hotkeys")]
+    keybindings: Keybindings,
+}
+
+impl Config {
+    pub fn new(path: &Path) -> Result<Self> {
+        let config = match File::open(path) {
+            Ok(mut file) => {
+                let mut toml = String::new();
+                file.read_to_string(&mut toml)?;
+                toml::from_str(&toml)?
+            }
+            Err(e) => {
+                println!("{}: {e}", path.display());
+                println!("Using default config.");
+                Config {
+                    bootrom: "dmg_boot.bin".to_string(),
+                    cgb_bootrom: default_cgb_bootrom(),
+                    saves_dir: "saves".into(),
+                    prints_dir: default_prints_dir(),
+                    autosave_interval: default_autosave_interval(),
+                    audio_volume: 100.0,
+                    scale: 3,
+                    rewind_buffer_mb: default_rewind_buffer_mb(),
+                    #[cfg(feature = "frontend")]
+                    keybindings: Keybindings::default(),
+                }
+            }
+        };
+        Ok(config)
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn keymap(&self, players: usize) -> KeyMap {
+        KeyMap::new(&self.keybindings, players)
+    }
+}
+
+fn default_cgb_bootrom() -> String {
+    "cgb_boot.bin".to_string()
+}
+
+fn default_rewind_buffer_mb() -> usize {
+    64
+}
+
+fn default_autosave_interval() -> u64 {
+    30
+}
+
+fn default_prints_dir() -> PathBuf {
+    "prints".into()
+}
@@ -0,0 +1,449 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{Result, bail};
+use rgb::{Access, CpuRegisters, Gameboy, WatchCondition, WatchHit, Watchpoint};
+
+struct Breakpoint {
+    addr: u16,
+    // Only break while this ROM bank is mapped at `addr`
+    bank: Option<usize>,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    break_requested: bool,
+    // Don't break again at the PC we just resumed from
+    resume_pc: Option<u16>,
+    target_frame: Option<u64>,
+    frame: u64,
+}
+
+// What the CPU should do after the REPL returns
+enum Resume {
+    Continue,
+    RunToFrame(u64),
+}
+
+const HELP: &str = "\
+Commands:
+  c, continue            Resume execution
+  s, step [n]            Execute n instructions (default 1)
+  n, next                Step over CALL/RST
+  o, out                 Run until the current function returns
+  f, frame <n>           Run until frame n starts
+  b, break [bank:]addr   Set a breakpoint
+  d, delete [bank:]addr  Delete a breakpoint
+  w, watch <r|w|rw> <addr>[-end] [cond]
+                         Set a watchpoint. cond is `changed`, `==xx` or `&mask==xx`
+  u, unwatch <n>         Delete watchpoint n
+  l, list                List breakpoints and watchpoints
+  r, regs                Show registers
+  set <reg> <value>      Set a register (a, f, b, c, d, e, h, l, af, bc, de, hl, sp, pc)
+  x <addr> [len]         Dump memory
+  dis [addr] [count]     Disassemble (default: at PC)
+  h, help                Show this message";
+
+impl Debugger {
+    pub fn new(break_on_start: bool) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            break_requested: break_on_start,
+            resume_pc: None,
+            target_frame: None,
+            frame: 0,
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn request_break(&mut self) {
+        self.break_requested = true;
+    }
+
+    // Runs one frame, entering the REPL whenever a breakpoint is hit. Without
+    // any breakpoints this is just `Gameboy::run_frame`.
+    pub fn run_frame(&mut self, gb: &mut Gameboy) -> Result<()> {
+        if self.breakpoints.is_empty()
+            && gb.watchpoints().is_empty()
+            && !self.break_requested
+            && self.target_frame.is_none()
+        {
+            gb.run_frame()?;
+            self.frame += 1;
+            return Ok(());
+        }
+        loop {
+            if self.break_requested || self.at_breakpoint(gb) {
+                self.break_requested = false;
+                self.repl(gb)?;
+            }
+            if self.step(gb)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step(&mut self, gb: &mut Gameboy) -> Result<bool> {
+        let frame_done = gb.step()?;
+        let hits = gb.take_watch_hits();
+        if !hits.is_empty() {
+            hits.iter().for_each(print_watch_hit);
+            self.break_requested = true;
+        }
+        if frame_done {
+            self.frame += 1;
+            if self.target_frame == Some(self.frame) {
+                self.target_frame = None;
+                self.break_requested = true;
+            }
+        }
+        Ok(frame_done)
+    }
+
+    fn at_breakpoint(&mut self, gb: &Gameboy) -> bool {
+        let pc = gb.registers().pc;
+        if self.resume_pc == Some(pc) {
+            return false;
+        }
+        self.resume_pc = None;
+        self.breakpoints
+            .iter()
+            .any(|bp| bp.addr == pc && bp.bank.is_none_or(|bank| gb.rom_bank(pc) == bank))
+    }
+
+    fn repl(&mut self, gb: &mut Gameboy) -> Result<()> {
+        self.print_location(gb);
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            print!("(rgb) ");
+            std::io::stdout().flush()?;
+            line.clear();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                // EOF, nothing more to read so just let the game run
+                self.breakpoints.clear();
+                while gb.remove_watchpoint(0).is_some() {}
+                break;
+            }
+            let args: Vec<&str> = line.split_whitespace().collect();
+            let Some((&cmd, args)) = args.split_first() else {
+                continue;
+            };
+            match self.command(gb, cmd, args) {
+                Ok(Some(Resume::Continue)) => break,
+                Ok(Some(Resume::RunToFrame(frame))) => {
+                    self.target_frame = Some(frame);
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => println!("{e}"),
+            }
+        }
+        self.resume_pc = Some(gb.registers().pc);
+        Ok(())
+    }
+
+    fn command(&mut self, gb: &mut Gameboy, cmd: &str, args: &[&str]) -> Result<Option<Resume>> {
+        match cmd {
+            "c" | "continue" => return Ok(Some(Resume::Continue)),
+            "s" | "step" => {
+                let count = args.first().map_or(Ok(1), |n| n.parse())?;
+                for _ in 0..count {
+                    self.step(gb)?;
+                }
+                self.print_location(gb);
+            }
+            "n" | "next" => {
+                self.step_over(gb)?;
+                self.print_location(gb);
+            }
+            "o" | "out" => {
+                self.step_out(gb)?;
+                self.print_location(gb);
+            }
+            "f" | "frame" => {
+                let [frame] = args else {
+                    bail!("Usage: frame <n>");
+                };
+                let frame = frame.parse()?;
+                if frame <= self.frame {
+                    bail!("Already at frame {}", self.frame);
+                }
+                return Ok(Some(Resume::RunToFrame(frame)));
+            }
+            "b" | "break" => {
+                let [location] = args else {
+                    bail!("Usage: break [bank:]addr");
+                };
+                let (addr, bank) = parse_location(location)?;
+                self.breakpoints.push(Breakpoint { addr, bank });
+            }
+            "d" | "delete" => {
+                let [location] = args else {
+                    bail!("Usage: delete [bank:]addr");
+                };
+                let (addr, bank) = parse_location(location)?;
+                let len = self.breakpoints.len();
+                self.breakpoints
+                    .retain(|bp| bp.addr != addr || (bank.is_some() && bp.bank != bank));
+                if self.breakpoints.len() == len {
+                    bail!("No breakpoint at {location}");
+                }
+            }
+            "w" | "watch" => {
+                let (access, range, condition) = match args {
+                    [access, range] => (access, range, "any"),
+                    [access, range, condition] => (access, range, *condition),
+                    _ => bail!("Usage: watch <r|w|rw> <addr>[-end] [cond]"),
+                };
+                let (read, write) = match *access {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" => (true, true),
+                    _ => bail!("Expected r, w or rw"),
+                };
+                let range = match range.split_once('-') {
+                    Some((start, end)) => parse_hex(start)?..=parse_hex(end)?,
+                    None => parse_hex(range)?..=parse_hex(range)?,
+                };
+                gb.add_watchpoint(Watchpoint {
+                    range,
+                    read,
+                    write,
+                    condition: parse_condition(condition)?,
+                });
+            }
+            "u" | "unwatch" => {
+                let [index] = args else {
+                    bail!("Usage: unwatch <n>");
+                };
+                if gb.remove_watchpoint(index.parse()?).is_none() {
+                    bail!("No watchpoint {index}");
+                }
+            }
+            "l" | "list" => {
+                for bp in &self.breakpoints {
+                    match bp.bank {
+                        Some(bank) => println!("break {bank:02x}:{:04x}", bp.addr),
+                        None => println!("break {:04x}", bp.addr),
+                    }
+                }
+                for (i, watchpoint) in gb.watchpoints().iter().enumerate() {
+                    let access = match (watchpoint.read, watchpoint.write) {
+                        (true, true) => "rw",
+                        (true, false) => "r",
+                        _ => "w",
+                    };
+                    let condition = match watchpoint.condition {
+                        WatchCondition::Any => "any".to_string(),
+                        WatchCondition::Changed => "changed".to_string(),
+                        WatchCondition::Value { mask, value } => {
+                            format!("&{mask:02x}=={value:02x}")
+                        }
+                    };
+                    println!(
+                        "watch {i}: {access} {:04x}-{:04x} {condition}",
+                        watchpoint.range.start(),
+                        watchpoint.range.end(),
+                    );
+                }
+            }
+            "r" | "regs" => print_registers(&gb.registers()),
+            "set" => {
+                let [reg, value] = args else {
+                    bail!("Usage: set <reg> <value>");
+                };
+                let mut regs = gb.registers();
+                set_register(&mut regs, reg, parse_hex(value)?)?;
+                gb.set_registers(regs);
+            }
+            "x" => {
+                // At most the whole address space, wrapping around at FFFF
+                let (addr, len) = match args {
+                    [addr] => (parse_hex(addr)?, 0x40),
+                    [addr, len] => (
+                        parse_hex(addr)?,
+                        u32::from_str_radix(hex_digits(len), 16)?.min(0x10000),
+                    ),
+                    _ => bail!("Usage: x <addr> [len]"),
+                };
+                for row in (0..len).step_by(16) {
+                    let row_addr = addr.wrapping_add(row as u16);
+                    let bytes: Vec<String> = (row..len.min(row + 16))
+                        .map(|i| format!("{:02x}", gb.read_memory(addr.wrapping_add(i as u16))))
+                        .collect();
+                    println!("{row_addr:04x}: {}", bytes.join(" "));
+                }
+            }
+            "dis" => {
+                let pc = gb.registers().pc;
+                let (mut addr, count) = match args {
+                    [] => (pc, 10),
+                    [addr] => (parse_hex(addr)?, 10),
+                    [addr, count] => (parse_hex(addr)?, count.parse()?),
+                    _ => bail!("Usage: dis [addr] [count]"),
+                };
+                for _ in 0..count {
+                    let (text, len) = gb.disassemble(addr);
+                    let marker = if addr == pc { ">" } else { " " };
+                    println!("{marker} {} {text}", format_addr(gb, addr));
+                    addr = addr.wrapping_add(len);
+                }
+            }
+            "h" | "help" => println!("{HELP}"),
+            _ => bail!("Unknown command: {cmd} (try `help`)"),
+        }
+        Ok(None)
+    }
+
+    // Runs CALL and RST instructions to completion, otherwise a single step
+    fn step_over(&mut self, gb: &mut Gameboy) -> Result<()> {
+        let start = gb.registers();
+        let (text, len) = gb.disassemble(start.pc);
+        self.step(gb)?;
+        if !(text.starts_with("CALL") || text.starts_with("RST")) {
+            return Ok(());
+        }
+        let return_addr = start.pc.wrapping_add(len);
+        self.resume_pc = None;
+        loop {
+            let regs = gb.registers();
+            if (regs.pc == return_addr && regs.sp >= start.sp) || self.at_breakpoint(gb) {
+                return Ok(());
+            }
+            self.step(gb)?;
+        }
+    }
+
+    // Runs until a return pops the frame that was current when this started
+    fn step_out(&mut self, gb: &mut Gameboy) -> Result<()> {
+        let start_sp = gb.registers().sp;
+        self.resume_pc = Some(gb.registers().pc);
+        loop {
+            let (text, _) = gb.disassemble(gb.registers().pc);
+            self.step(gb)?;
+            if text.starts_with("RET") && gb.registers().sp > start_sp {
+                return Ok(());
+            }
+            if self.at_breakpoint(gb) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn print_location(&self, gb: &Gameboy) {
+        let pc = gb.registers().pc;
+        let (text, _) = gb.disassemble(pc);
+        println!("[frame {}] {} {text}", self.frame, format_addr(gb, pc));
+    }
+}
+
+fn format_addr(gb: &Gameboy, addr: u16) -> String {
+    if addr < 0x8000 {
+        format!("{:02x}:{addr:04x}", gb.rom_bank(addr))
+    } else {
+        format!("{addr:04x}")
+    }
+}
+
+fn print_watch_hit(hit: &WatchHit) {
+    let pc = match hit.bank {
+        Some(bank) => format!("{bank:02x}:{:04x}", hit.pc),
+        None => format!("{:04x}", hit.pc),
+    };
+    match hit.access {
+        Access::Read => println!("{pc}: read {:04x} = {:02x}", hit.addr, hit.new),
+        Access::Write => println!(
+            "{pc}: write {:04x}: {:02x} -> {:02x}",
+            hit.addr, hit.old, hit.new
+        ),
+    }
+}
+
+fn print_registers(regs: &CpuRegisters) {
+    println!(
+        "AF={:02x}{:02x} BC={:02x}{:02x} DE={:02x}{:02x} HL={:02x}{:02x} SP={:04x} PC={:04x}",
+        regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l, regs.sp, regs.pc
+    );
+    println!(
+        "Flags: {}{}{}{}",
+        if regs.f & 0x80 != 0 { 'Z' } else { '-' },
+        if regs.f & 0x40 != 0 { 'N' } else { '-' },
+        if regs.f & 0x20 != 0 { 'H' } else { '-' },
+        if regs.f & 0x10 != 0 { 'C' } else { '-' },
+    );
+}
+
+fn set_register(regs: &mut CpuRegisters, reg: &str, value: u16) -> Result<()> {
+    let [hi, lo] = value.to_be_bytes();
+    let byte = || {
+        if hi == 0 {
+            Ok(lo)
+        } else {
+            bail!("{reg} is an 8-bit register")
+        }
+    };
+    match reg.to_lowercase().as_str() {
+        "a" => regs.a = byte()?,
+        // The low nibble of F always reads as zero
+        "f" => regs.f = byte()? & 0xf0,
+        "b" => regs.b = byte()?,
+        "c" => regs.c = byte()?,
+        "d" => regs.d = byte()?,
+        "e" => regs.e = byte()?,
+        "h" => regs.h = byte()?,
+        "l" => regs.l = byte()?,
+        "af" => (regs.a, regs.f) = (hi, lo & 0xf0),
+        "bc" => (regs.b, regs.c) = (hi, lo),
+        "de" => (regs.d, regs.e) = (hi, lo),
+        "hl" => (regs.h, regs.l) = (hi, lo),
+        "sp" => regs.sp = value,
+        "pc" => regs.pc = value,
+        _ => bail!("Unknown register: {reg}"),
+    }
+    Ok(())
+}
+
+// Hex with an optional `$` or `0x` prefix
+fn parse_hex(s: &str) -> Result<u16> {
+    Ok(u16::from_str_radix(hex_digits(s), 16)?)
+}
+
+fn hex_digits(s: &str) -> &str {
+    s.strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s)
+}
+
+fn parse_condition(s: &str) -> Result<WatchCondition> {
+    if s == "any" {
+        return Ok(WatchCondition::Any);
+    }
+    if s == "changed" {
+        return Ok(WatchCondition::Changed);
+    }
+    let Some((mask, value)) = s.split_once("==") else {
+        bail!("Invalid condition: {s}");
+    };
+    let mask = match mask.strip_prefix('&') {
+        Some(mask) => parse_hex(mask)?,
+        None if mask.is_empty() => 0xff,
+        None => bail!("Invalid condition: {s}"),
+    };
+    let (Ok(mask), Ok(value)) = (u8::try_from(mask), u8::try_from(parse_hex(value)?)) else {
+        bail!("Condition values must be 8-bit");
+    };
+    Ok(WatchCondition::Value { mask, value })
+}
+
+fn parse_location(s: &str) -> Result<(u16, Option<usize>)> {
+    match s.split_once(':') {
+        Some((bank, addr)) => {
+            let addr = parse_hex(addr)?;
+            if addr >= 0x8000 {
+                bail!("ROM banks only apply to 0000-7fff");
+            }
+            Ok((addr, Some(parse_hex(bank)? as usize)))
+        }
+        None => Ok((parse_hex(s)?, None)),
+    }
+}
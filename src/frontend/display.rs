@@ -1,11 +1,13 @@
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::cpu::Cpu;
-use crate::hotkeys::{Hotkey, KeyMap};
+use super::Machine;
+use super::hotkeys::{Hotkey, KeyMap};
+use super::overlay;
 
 use anyhow::Result;
 use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use rgb::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use spin_sleep_util::Interval;
 use winit::{
     dpi::LogicalSize,
@@ -24,28 +26,45 @@ pub enum DisplayEvent {
 }
 
 pub struct Display {
-    surface: Option<Surface<160, 144>>,
+    surface: Option<Surface>,
     keymap: KeyMap,
     scale_factor: u32,
+    // Linked Game Boys are shown side by side, two to a row
+    screens: usize,
     limit_framerate: bool,
     frame_limiter: Interval,
     instant: Instant,
+    // Drawn over the screens until cleared
+    error: Option<String>,
 }
 
 impl Display {
-    pub fn new(keymap: KeyMap, scale_factor: u32) -> Self {
+    pub fn new(keymap: KeyMap, scale_factor: u32, screens: usize) -> Self {
         Self {
             surface: None,
             keymap,
             scale_factor,
+            screens,
             limit_framerate: true,
             frame_limiter: spin_sleep_util::interval(Duration::from_secs_f64(1.0 / FRAMERATE)),
             instant: Instant::now(),
+            error: None,
         }
     }
 
+    fn columns(&self) -> usize {
+        self.screens.min(2)
+    }
+
     pub fn reinit_surface(&mut self, event_loop: &ActiveEventLoop) -> Result<()> {
-        self.surface = Some(Surface::new(event_loop, self.scale_factor)?);
+        let width = SCREEN_WIDTH * self.columns();
+        let height = SCREEN_HEIGHT * self.screens.div_ceil(2);
+        self.surface = Some(Surface::new(
+            event_loop,
+            width as u32,
+            height as u32,
+            self.scale_factor,
+        )?);
         Ok(())
     }
 
@@ -54,17 +73,35 @@ impl Display {
         event_loop.exit();
     }
 
-    pub fn draw_frame(&mut self, cpu: &mut Cpu) -> Result<()> {
+    // `run_frame` emulates one frame and consumes its audio
+    pub fn draw_frame(
+        &mut self,
+        machine: &mut Machine,
+        mut run_frame: impl FnMut(&mut Machine) -> Result<()>,
+    ) -> Result<()> {
+        let columns = self.columns();
         if let Some(surface) = &mut self.surface {
             if self.limit_framerate {
-                cpu.run_frame()?;
+                run_frame(machine)?;
                 self.frame_limiter.tick();
             } else {
                 while self.instant.elapsed() < Duration::from_secs_f64(1.0 / 480.0) {
-                    cpu.run_frame()?;
+                    run_frame(machine)?;
                 }
             }
-            cpu.ppu_mut().render(&mut surface.pixels)?;
+            let frame = surface.pixels.frame_mut();
+            let row = SCREEN_WIDTH * 4;
+            for (i, gb) in machine.gameboys().iter().enumerate() {
+                let (column, top) = (i % columns, i / columns * SCREEN_HEIGHT);
+                for (y, line) in gb.framebuffer().chunks_exact(row).enumerate() {
+                    let start = ((top + y) * columns + column) * row;
+                    frame[start..start + row].copy_from_slice(line);
+                }
+            }
+            if let Some(error) = &self.error {
+                overlay::draw_message(frame, SCREEN_WIDTH * columns, error);
+            }
+            surface.pixels.render()?;
             self.instant = Instant::now();
         }
         Ok(())
@@ -108,20 +145,40 @@ impl Display {
         }
     }
 
+    // Shows the error over the screens from the next redraw on, and its first
+    // line in the title bar
+    pub fn show_error(&mut self, error: Option<&str>) {
+        self.error = error.map(str::to_owned);
+        if let Some(surface) = &self.surface {
+            match error.and_then(|error| error.lines().next()) {
+                Some(error) => surface.window.set_title(&format!("rgb - {error}")),
+                None => surface.window.set_title("rgb"),
+            }
+        }
+    }
+
     pub fn toggle_frame_limiter(&mut self) {
         self.limit_framerate = !self.limit_framerate;
     }
 }
 
-struct Surface<const W: u32, const H: u32> {
+struct Surface {
     window: Arc<Window>,
     pixels: Pixels<'static>,
 }
 
-impl<const W: u32, const H: u32> Surface<W, H> {
-    fn new(event_loop: &ActiveEventLoop, scale_factor: u32) -> Result<Self> {
+impl Surface {
+    fn new(
+        event_loop: &ActiveEventLoop,
+        width: u32,
+        height: u32,
+        scale_factor: u32,
+    ) -> Result<Self> {
         event_loop.set_control_flow(ControlFlow::Poll);
-        let size = LogicalSize::new((W * scale_factor) as f64, (H * scale_factor) as f64);
+        let size = LogicalSize::new(
+            (width * scale_factor) as f64,
+            (height * scale_factor) as f64,
+        );
         let window = Arc::new(
             event_loop.create_window(
                 Window::default_attributes()
@@ -139,7 +196,7 @@ impl<const W: u32, const H: u32> Surface<W, H> {
                 physical_window_size.height,
                 Arc::clone(&window),
             );
-            PixelsBuilder::new(W, H, surface_texture)
+            PixelsBuilder::new(width, height, surface_texture)
                 .enable_vsync(false)
                 .build()?
         };
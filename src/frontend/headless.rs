@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use rgb::{Gameboy, JoypadButton, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use super::config::{Args, Config};
+use super::debugger::Debugger;
+
+struct InputEvent {
+    frame: u64,
+    button: JoypadButton,
+    pressed: bool,
+}
+
+pub fn run(args: Args, config: Config) -> Result<()> {
+    let mut gb = super::new_gameboy(&args, &config)?;
+    let mut debugger = Debugger::new(args.debug_break);
+    let mut inputs = match &args.input {
+        Some(path) => parse_input_script(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("{}", path.display()))?,
+        None => Vec::new(),
+    }
+    .into_iter()
+    .peekable();
+
+    for frame in 0..args.frames {
+        while let Some(event) = inputs.next_if(|event| event.frame <= frame) {
+            gb.update_button(event.button, event.pressed);
+        }
+        debugger.run_frame(&mut gb)?;
+        // Nothing consumes audio in headless mode
+        gb.audio_samples().for_each(drop);
+    }
+
+    if let Some(path) = &args.screenshot {
+        write_screenshot(&gb, path)?;
+    }
+    Ok(())
+}
+
+// One event per line: `<frame> <press|release> <button>`, e.g. `60 press start`.
+// Blank lines and lines starting with `#` are ignored.
+fn parse_input_script(script: &str) -> Result<Vec<InputEvent>> {
+    let mut events = Vec::new();
+    for (line_num, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_line = || -> Result<InputEvent> {
+            let [frame, action, button] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                bail!("Expected `<frame> <press|release> <button>`");
+            };
+            let pressed = match action {
+                "press" => true,
+                "release" => false,
+                _ => bail!("Invalid action: {action}"),
+            };
+            let button = match button.to_lowercase().as_str() {
+                "up" => JoypadButton::Up,
+                "down" => JoypadButton::Down,
+                "left" => JoypadButton::Left,
+                "right" => JoypadButton::Right,
+                "a" => JoypadButton::A,
+                "b" => JoypadButton::B,
+                "start" => JoypadButton::Start,
+                "select" => JoypadButton::Select,
+                _ => bail!("Invalid button: {button}"),
+            };
+            Ok(InputEvent {
+                frame: frame.parse()?,
+                button,
+                pressed,
+            })
+        };
+        events.push(parse_line().with_context(|| format!("line {}", line_num + 1))?);
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+fn write_screenshot(gb: &Gameboy, path: &Path) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(gb.framebuffer())?;
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use rgb::JoypadButton;
+use serde::Deserialize;
+use serde::de::{Deserializer, IntoDeserializer};
+use winit::keyboard::KeyCode as WinitKeyCode;
+
+pub struct KeyMap {
+    map: HashMap<WinitKeyCode, Hotkey>,
+}
+
+impl KeyMap {
+    // Only the keys of players that are there are mapped. Earlier players, then
+    // emulator hotkeys, win when a key is bound twice.
+    pub fn new(keys: &Keybindings, players: usize) -> Self {
+        let joypads = [&keys.joypad, &keys.joypad2, &keys.joypad3, &keys.joypad4];
+        let joypads = joypads
+            .into_iter()
+            .enumerate()
+            .take(players)
+            .rev()
+            .flat_map(|(player, joypad)| {
+                joypad
+                    .buttons()
+                    .map(|(k, b)| (k, Hotkey::Joypad(player, b)))
+            });
+        Self {
+            map: joypads
+                .chain([
+                    (keys.emu.toggle_frame_limiter, Hotkey::ToggleFrameLimiter),
+                    (keys.emu.save_state, Hotkey::SaveState),
+                    (keys.emu.load_state, Hotkey::LoadState),
+                    (keys.emu.next_slot, Hotkey::NextSlot),
+                    (keys.emu.prev_slot, Hotkey::PrevSlot),
+                    (keys.emu.rewind, Hotkey::Rewind),
+                    (keys.emu.debug, Hotkey::Debug),
+                ])
+                .map(|(k, h)| (k.into(), h))
+                .collect(),
+        }
+    }
+
+    pub fn get_hotkey(&self, key: WinitKeyCode) -> Option<Hotkey> {
+        self.map.get(&key).copied()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Hotkey {
+    // Player number from 0, and button
+    Joypad(usize, JoypadButton),
+    ToggleFrameLimiter,
+    SaveState,
+    LoadState,
+    NextSlot,
+    PrevSlot,
+    Rewind,
+    Debug,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Hash, Eq, PartialEq)]
+#[serde(remote = "Self")]
+pub enum KeyCode {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+
+    Up,
+    Down,
+    Left,
+    Right,
+
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+
+    Enter,
+    Space,
+    Tab,
+    Backspace,
+
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+}
+
+impl From<KeyCode> for WinitKeyCode {
+    fn from(keycode: KeyCode) -> Self {
+        match keycode {
+            KeyCode::A => Self::KeyA,
+            KeyCode::B => Self::KeyB,
+            KeyCode::C => Self::KeyC,
+            KeyCode::D => Self::KeyD,
+            KeyCode::E => Self::KeyE,
+            KeyCode::F => Self::KeyF,
+            KeyCode::G => Self::KeyG,
+            KeyCode::H => Self::KeyH,
+            KeyCode::I => Self::KeyI,
+            KeyCode::J => Self::KeyJ,
+            KeyCode::K => Self::KeyK,
+            KeyCode::L => Self::KeyL,
+            KeyCode::M => Self::KeyM,
+            KeyCode::N => Self::KeyN,
+            KeyCode::O => Self::KeyO,
+            KeyCode::P => Self::KeyP,
+            KeyCode::Q => Self::KeyQ,
+            KeyCode::R => Self::KeyR,
+            KeyCode::S => Self::KeyS,
+            KeyCode::T => Self::KeyT,
+            KeyCode::U => Self::KeyU,
+            KeyCode::V => Self::KeyV,
+            KeyCode::W => Self::KeyW,
+            KeyCode::X => Self::KeyX,
+            KeyCode::Y => Self::KeyY,
+            KeyCode::Z => Self::KeyZ,
+
+            KeyCode::Up => Self::ArrowUp,
+            KeyCode::Down => Self::ArrowDown,
+            KeyCode::Left => Self::ArrowLeft,
+            KeyCode::Right => Self::ArrowRight,
+
+            KeyCode::F1 => Self::F1,
+            KeyCode::F2 => Self::F2,
+            KeyCode::F3 => Self::F3,
+            KeyCode::F4 => Self::F4,
+            KeyCode::F5 => Self::F5,
+            KeyCode::F6 => Self::F6,
+            KeyCode::F7 => Self::F7,
+            KeyCode::F8 => Self::F8,
+            KeyCode::F9 => Self::F9,
+            KeyCode::F10 => Self::F10,
+            KeyCode::F11 => Self::F11,
+            KeyCode::F12 => Self::F12,
+
+            KeyCode::Enter => Self::Enter,
+            KeyCode::Space => Self::Space,
+            KeyCode::Tab => Self::Tab,
+            KeyCode::Backspace => Self::Backspace,
+
+            KeyCode::Numpad0 => Self::Numpad0,
+            KeyCode::Numpad1 => Self::Numpad1,
+            KeyCode::Numpad2 => Self::Numpad2,
+            KeyCode::Numpad3 => Self::Numpad3,
+            KeyCode::Numpad4 => Self::Numpad4,
+            KeyCode::Numpad5 => Self::Numpad5,
+            KeyCode::Numpad6 => Self::Numpad6,
+            KeyCode::Numpad7 => Self::Numpad7,
+            KeyCode::Numpad8 => Self::Numpad8,
+            KeyCode::Numpad9 => Self::Numpad9,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fn capitalize(s: &str) -> String {
+            let mut c = s.chars();
+            match c.next() {
+                None => String::new(),
+                Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
+            }
+        }
+
+        let val = String::deserialize(deserializer)?;
+        Self::deserialize(capitalize(&val).into_deserializer())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Keybindings {
+    joypad: JoypadBindings,
+    #[serde(default = "JoypadBindings::player2")]
+    joypad2: JoypadBindings,
+    #[serde(default = "JoypadBindings::player3")]
+    joypad3: JoypadBindings,
+    #[serde(default = "JoypadBindings::player4")]
+    joypad4: JoypadBindings,
+    emu: EmuBindings,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            joypad: JoypadBindings::default(),
+            joypad2: JoypadBindings::player2(),
+            joypad3: JoypadBindings::player3(),
+            joypad4: JoypadBindings::player4(),
+            emu: EmuBindings::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoypadBindings {
+    up: KeyCode,
+    down: KeyCode,
+    left: KeyCode,
+    right: KeyCode,
+    a: KeyCode,
+    b: KeyCode,
+    start: KeyCode,
+    select: KeyCode,
+}
+
+impl JoypadBindings {
+    fn buttons(&self) -> [(KeyCode, JoypadButton); 8] {
+        [
+            (self.up, JoypadButton::Up),
+            (self.down, JoypadButton::Down),
+            (self.left, JoypadButton::Left),
+            (self.right, JoypadButton::Right),
+            (self.a, JoypadButton::A),
+            (self.b, JoypadButton::B),
+            (self.start, JoypadButton::Start),
+            (self.select, JoypadButton::Select),
+        ]
+    }
+
+    fn player2() -> Self {
+        JoypadBindings {
+            up: KeyCode::W,
+            down: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+            a: KeyCode::K,
+            b: KeyCode::J,
+            start: KeyCode::I,
+            select: KeyCode::U,
+        }
+    }
+
+    fn player3() -> Self {
+        JoypadBindings {
+            up: KeyCode::T,
+            down: KeyCode::G,
+            left: KeyCode::F,
+            right: KeyCode::H,
+            a: KeyCode::V,
+            b: KeyCode::C,
+            start: KeyCode::R,
+            select: KeyCode::E,
+        }
+    }
+
+    fn player4() -> Self {
+        JoypadBindings {
+            up: KeyCode::Numpad8,
+            down: KeyCode::Numpad5,
+            left: KeyCode::Numpad4,
+            right: KeyCode::Numpad6,
+            a: KeyCode::Numpad3,
+            b: KeyCode::Numpad2,
+            start: KeyCode::Numpad9,
+            select: KeyCode::Numpad7,
+        }
+    }
+}
+
+impl Default for JoypadBindings {
+    fn default() -> Self {
+        JoypadBindings {
+            up: KeyCode::Up,
+            down: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            a: KeyCode::X,
+            b: KeyCode::Z,
+            start: KeyCode::Enter,
+            select: KeyCode::Tab,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EmuBindings {
+    toggle_frame_limiter: KeyCode,
+    save_state: KeyCode,
+    load_state: KeyCode,
+    next_slot: KeyCode,
+    prev_slot: KeyCode,
+    rewind: KeyCode,
+    debug: KeyCode,
+}
+
+impl Default for EmuBindings {
+    fn default() -> Self {
+        EmuBindings {
+            toggle_frame_limiter: KeyCode::Space,
+            save_state: KeyCode::F1,
+            load_state: KeyCode::F2,
+            next_slot: KeyCode::F4,
+            prev_slot: KeyCode::F3,
+            rewind: KeyCode::Backspace,
+            debug: KeyCode::F12,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use rgb::{CartridgeHeader, CgbSupport, Gameboy};
+
+pub fn run(roms: &[PathBuf]) -> Result<()> {
+    let mut failed = 0;
+    for (i, path) in roms.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", path.display());
+        if let Err(e) = print_info(path) {
+            println!("  Error: {e:#}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} ROMs could not be read", roms.len());
+    }
+    Ok(())
+}
+
+fn print_info(path: &Path) -> Result<()> {
+    let rom = std::fs::read(path).context("Failed to read")?;
+    let header = CartridgeHeader::parse(&rom)?;
+    let valid = |valid: bool| if valid { "ok" } else { "MISMATCH" };
+
+    println!("  Title:            {}", header.title);
+    if let Some(code) = &header.manufacturer_code {
+        println!("  Manufacturer:     {code}");
+    }
+    let cgb = match header.cgb {
+        CgbSupport::None => "no",
+        CgbSupport::Enhanced => "enhanced",
+        CgbSupport::Only => "required",
+    };
+    println!("  CGB:              {cgb}");
+    println!(
+        "  SGB:              {}",
+        if header.sgb { "yes" } else { "no" }
+    );
+    println!(
+        "  Type:             {:02x} ({})",
+        header.cartridge_type,
+        header.cartridge_type_name().unwrap_or("unknown")
+    );
+    match header.rom_banks() {
+        Some(banks) => println!(
+            "  ROM size:         {} KiB ({banks} banks), file is {} KiB",
+            banks as usize * 16,
+            rom.len() / 1024
+        ),
+        None => println!("  ROM size:         invalid ({:02x})", header.rom_size),
+    }
+    match header.ram_bytes() {
+        Some(bytes) => println!("  RAM size:         {} KiB", bytes / 1024),
+        None => println!("  RAM size:         invalid ({:02x})", header.ram_size),
+    }
+    let features: Vec<&str> = [
+        (header.has_ram(), "RAM"),
+        (header.has_battery(), "battery"),
+        (header.has_rtc(), "RTC"),
+    ]
+    .into_iter()
+    .filter_map(|(present, feature)| present.then_some(feature))
+    .collect();
+    if !features.is_empty() {
+        println!("  Features:         {}", features.join(", "));
+    }
+    let destination = if header.japanese { "Japan" } else { "overseas" };
+    println!("  Destination:      {destination}");
+    if header.old_licensee == 0x33 {
+        println!("  Licensee:         {} (new)", header.new_licensee);
+    } else {
+        println!("  Licensee:         {:02x} (old)", header.old_licensee);
+    }
+    println!("  Version:          {}", header.version);
+    println!(
+        "  Header checksum:  {:02x} {}",
+        header.header_checksum,
+        valid(header.header_checksum_valid())
+    );
+    println!(
+        "  Global checksum:  {:04x} {}",
+        header.global_checksum,
+        valid(header.global_checksum_valid())
+    );
+    // Whether the emulator would actually run it
+    match Gameboy::new(rom, None) {
+        Ok(gb) => println!("  Emulated as:      {:?}", gb.model()),
+        Err(e) => println!("  Emulated as:      unsupported, {e}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use rgb::SerialDevice;
+
+// Commands of the BGB link protocol (https://bgb.bircd.org/bgblink.html)
+const VERSION: u8 = 1;
+const SYNC1: u8 = 104;
+const SYNC2: u8 = 105;
+const SYNC3: u8 = 106;
+const STATUS: u8 = 108;
+const WANT_DISCONNECT: u8 = 109;
+
+// Timestamps count a 2 MiHz clock, and only have 31 bits. An mcycle is two
+// ticks, or one in double speed.
+const TIMESTAMP_MASK: u32 = 0x7fff_ffff;
+// How often our timestamp is sent, and how far we may run ahead of the last
+// one received before waiting for the peer to catch up. While waiting for
+// the peer to clock a transfer, or for its reply to one we clocked, it has
+// to arrive right on time, or the game may not be ready for the next one.
+const SYNC_INTERVAL: u32 = 4096;
+const MAX_AHEAD: i32 = 4 * SYNC_INTERVAL as i32;
+const MAX_AHEAD_PASSIVE: i32 = 512;
+// A peer that doesn't answer for this long is stalled (paused, in a
+// debugger), and isn't waited for any longer
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Packet {
+    command: u8,
+    b2: u8,
+    b3: u8,
+    b4: u8,
+    timestamp: u32,
+}
+
+impl Packet {
+    fn new(command: u8, b2: u8, b3: u8, timestamp: u32) -> Self {
+        Self {
+            command,
+            b2,
+            b3,
+            b4: 0,
+            timestamp,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 8] {
+        let [t0, t1, t2, t3] = self.timestamp.to_le_bytes();
+        [self.command, self.b2, self.b3, self.b4, t0, t1, t2, t3]
+    }
+
+    fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            command: bytes[0],
+            b2: bytes[1],
+            b3: bytes[2],
+            b4: bytes[3],
+            timestamp: u32::from_le_bytes(bytes[4..].try_into().unwrap()),
+        }
+    }
+}
+
+// Signed distance between two timestamps, accounting for wraparound
+fn elapsed(from: u32, to: u32) -> i32 {
+    ((to.wrapping_sub(from) << 1) as i32) >> 1
+}
+
+// A link cable to another emulator (BGB, or another instance of this one)
+// over TCP. Packets are read on a separate thread, so they can be polled
+// every mcycle without blocking.
+pub struct BgbLink {
+    stream: TcpStream,
+    packets: Receiver<Packet>,
+    connected: bool,
+    time: u32,
+    last_sent: u32,
+    remote_time: u32,
+    remote_paused: bool,
+    // The connecting side switches to the listening side's clock, so that
+    // timestamps from both mean the same thing
+    adopt_clock: bool,
+    // A transfer clocked by the peer, delivered once we reach its timestamp
+    incoming: Option<Packet>,
+    // When we clocked a transfer the peer hasn't replied to yet, and its
+    // reply once it has
+    pending: Option<Instant>,
+    reply: Option<u8>,
+}
+
+impl BgbLink {
+    pub fn listen(addr: &str) -> Result<Self> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("Failed to listen on {addr}"))?;
+        println!("Waiting for a link cable connection on {addr}");
+        Self::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> Result<Self> {
+        let (stream, peer) = listener.accept()?;
+        println!("Link cable connected to {peer}");
+        Self::new(stream, false)
+    }
+
+    pub fn connect(addr: &str) -> Result<Self> {
+        let stream =
+            TcpStream::connect(addr).with_context(|| format!("Failed to connect to {addr}"))?;
+        println!("Link cable connected to {addr}");
+        Self::new(stream, true)
+    }
+
+    fn new(mut stream: TcpStream, adopt_clock: bool) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.write_all(&Packet::new(VERSION, 1, 4, 0).to_bytes())?;
+
+        let mut reader = stream.try_clone()?;
+        reader.set_read_timeout(Some(TIMEOUT * 5))?;
+        let mut bytes = [0; 8];
+        reader
+            .read_exact(&mut bytes)
+            .context("The link cable peer didn't send its version")?;
+        // Only the major and minor version matter
+        if bytes[..3] != [VERSION, 1, 4] {
+            bail!("Unsupported link cable protocol version {bytes:02x?}");
+        }
+        reader.set_read_timeout(None)?;
+
+        // Running, not paused, no reconnect support
+        stream.write_all(&Packet::new(STATUS, 1, 0, 0).to_bytes())?;
+        stream.write_all(&Packet::new(SYNC3, 0, 0, 0).to_bytes())?;
+
+        let (sender, packets) = mpsc::channel();
+        thread::spawn(move || {
+            let mut bytes = [0; 8];
+            while reader.read_exact(&mut bytes).is_ok() {
+                if sender.send(Packet::from_bytes(bytes)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            stream,
+            packets,
+            connected: true,
+            time: 0,
+            last_sent: 0,
+            remote_time: 0,
+            remote_paused: false,
+            adopt_clock,
+            incoming: None,
+            pending: None,
+            reply: None,
+        })
+    }
+
+    fn disconnect(&mut self) {
+        if self.connected {
+            println!("Link cable disconnected");
+            let _ = self
+                .stream
+                .write_all(&Packet::new(WANT_DISCONNECT, 0, 0, 0).to_bytes());
+            let _ = self.stream.shutdown(Shutdown::Both);
+            self.connected = false;
+            self.incoming = None;
+        }
+    }
+
+    fn send(&mut self, packet: Packet) {
+        if self.connected && self.stream.write_all(&packet.to_bytes()).is_err() {
+            self.disconnect();
+        }
+    }
+
+    fn send_timestamp(&mut self) {
+        self.send(Packet::new(SYNC3, 0, 0, self.time));
+        self.last_sent = self.time;
+    }
+
+    fn remote_timestamp(&mut self, timestamp: u32) {
+        if std::mem::take(&mut self.adopt_clock) {
+            self.time = timestamp;
+            self.last_sent = timestamp;
+        }
+        self.remote_time = timestamp;
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        match packet.command {
+            SYNC1 => {
+                self.remote_timestamp(packet.timestamp);
+                self.incoming = Some(packet);
+            }
+            // The peer shifted a byte back for the transfer we clocked, or just
+            // acknowledged it if it wasn't waiting for one
+            SYNC2 if self.pending.is_some() => self.reply = Some(packet.b2),
+            SYNC3 if packet.b2 == 1 && self.pending.is_some() => self.reply = Some(0xff),
+            SYNC3 if packet.b2 == 0 => self.remote_timestamp(packet.timestamp),
+            STATUS => self.remote_paused = packet.b2 & 0b11 != 0b01,
+            WANT_DISCONNECT => self.disconnect(),
+            // Joypad updates, stray replies and anything unknown
+            _ => {}
+        }
+    }
+
+    fn max_ahead(&self, passive: bool) -> i32 {
+        if passive || (self.pending.is_some() && self.reply.is_none()) {
+            MAX_AHEAD_PASSIVE
+        } else {
+            MAX_AHEAD
+        }
+    }
+
+    // Waits for the next packet. Returns None on a timeout or disconnect.
+    fn wait(&mut self) -> Option<Packet> {
+        match self.packets.recv_timeout(TIMEOUT) {
+            Ok(packet) => Some(packet),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.disconnect();
+                None
+            }
+        }
+    }
+}
+
+impl SerialDevice for BgbLink {
+    // The reply comes in with the other packets in `tick`. If both sides
+    // clock a transfer at once, `tick` acks the peer's like any other that
+    // finds us not waiting.
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        if !self.connected {
+            return Some(0xff);
+        }
+        self.send(Packet::new(SYNC1, byte, 0x81, self.time));
+        self.last_sent = self.time;
+        self.pending = Some(Instant::now());
+        self.reply = None;
+        None
+    }
+
+    fn poll_reply(&mut self) -> Option<u8> {
+        let reply = match self.reply.take() {
+            Some(reply) => reply,
+            None if !self.connected => 0xff,
+            None if self.pending?.elapsed() > TIMEOUT => {
+                log::warn!("The link cable peer didn't answer a transfer");
+                0xff
+            }
+            None => return None,
+        };
+        self.pending = None;
+        Some(reply)
+    }
+
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        let time = self.time;
+        let packet = self
+            .incoming
+            .take_if(|packet| elapsed(packet.timestamp, time) >= 0)?;
+        self.send(Packet::new(SYNC2, byte, 0x80, 0));
+        Some(packet.b2)
+    }
+
+    fn tick(&mut self, passive: bool, double_speed: bool) {
+        if !self.connected {
+            return;
+        }
+        let ticks = if double_speed { 1 } else { 2 };
+        self.time = (self.time + ticks) & TIMESTAMP_MASK;
+        if elapsed(self.last_sent, self.time) >= SYNC_INTERVAL as i32 {
+            self.send_timestamp();
+        }
+        loop {
+            match self.packets.try_recv() {
+                Ok(packet) => self.receive(packet),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+
+        while self.connected
+            && !self.remote_paused
+            && elapsed(self.remote_time, self.time) > self.max_ahead(passive)
+        {
+            self.send_timestamp();
+            match self.wait() {
+                Some(packet) => self.receive(packet),
+                None => self.remote_time = self.time,
+            }
+        }
+
+        // The peer clocked a transfer we weren't ready for
+        let time = self.time;
+        if !passive
+            && self
+                .incoming
+                .take_if(|packet| elapsed(packet.timestamp, time) >= 0)
+                .is_some()
+        {
+            self.send(Packet::new(SYNC3, 1, 0, 0));
+        }
+    }
+}
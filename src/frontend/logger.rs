@@ -0,0 +1,42 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Prints warnings from the core to stderr. A misbehaving game tends to repeat
+// the same mistake every frame, so each message is only printed once.
+struct Logger {
+    seen: Mutex<BTreeSet<String>>,
+}
+
+static LOGGER: Logger = Logger {
+    seen: Mutex::new(BTreeSet::new()),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        if self.seen.lock().unwrap().insert(message.clone()) {
+            let level = match record.level() {
+                Level::Error => "error",
+                _ => "warning",
+            };
+            eprintln!("{level}: {message}");
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Warn);
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{Result, bail};
+use rgb::{FourPlayerAdapter, Gameboy, LinkedGameboys};
+
+use super::config::{Args, Config};
+use super::{load_rom, new_gameboy};
+
+// One Game Boy, two linked together with `--link`, or up to four on an
+// adapter with `--four-player`. There's only ever one of these, so the size
+// difference doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Machine {
+    Single(Gameboy),
+    Linked(LinkedGameboys),
+    FourPlayer(FourPlayerAdapter),
+}
+
+impl Machine {
+    pub fn new(args: &Args, config: &Config) -> Result<Self> {
+        if args.link && args.linked_cartridges().len() > 1 {
+            bail!("--link takes two ROMs");
+        }
+        let mut gameboys = vec![new_gameboy(args, config)?];
+        for path in args.linked_cartridges() {
+            gameboys.push(load_rom(path, args, config)?);
+        }
+        Ok(if args.four_player {
+            Self::FourPlayer(FourPlayerAdapter::new(gameboys)?)
+        } else if let Some(player2) = gameboys.pop_if(|_| args.link) {
+            Self::Linked(LinkedGameboys::new([gameboys.remove(0), player2]))
+        } else {
+            Self::Single(gameboys.remove(0))
+        })
+    }
+
+    pub fn gameboys(&self) -> &[Gameboy] {
+        match self {
+            Self::Single(gb) => std::slice::from_ref(gb),
+            Self::Linked(linked) => linked.gameboys(),
+            Self::FourPlayer(adapter) => adapter.gameboys(),
+        }
+    }
+
+    pub fn gameboys_mut(&mut self) -> &mut [Gameboy] {
+        match self {
+            Self::Single(gb) => std::slice::from_mut(gb),
+            Self::Linked(linked) => linked.gameboys_mut(),
+            Self::FourPlayer(adapter) => adapter.gameboys_mut(),
+        }
+    }
+}
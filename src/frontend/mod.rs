@@ -0,0 +1,73 @@
+#[cfg(feature = "frontend")]
+mod app;
+#[cfg(feature = "frontend")]
+mod audio;
+mod config;
+mod debugger;
+#[cfg(feature = "frontend")]
+mod display;
+pub mod headless;
+#[cfg(feature = "frontend")]
+mod hotkeys;
+pub mod info;
+mod link;
+pub mod logger;
+#[cfg(feature = "frontend")]
+mod machine;
+#[cfg(feature = "frontend")]
+mod overlay;
+mod printer;
+#[cfg(feature = "frontend")]
+mod rewind;
+#[cfg(feature = "frontend")]
+mod save;
+
+#[cfg(feature = "frontend")]
+pub use app::App;
+pub use config::{Args, Command, Config};
+#[cfg(feature = "frontend")]
+pub use machine::Machine;
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use link::BgbLink;
+use printer::Printer;
+use rgb::{Gameboy, Model};
+
+// With `--link` or `--four-player`, this is player 1
+fn new_gameboy(args: &Args, config: &Config) -> Result<Gameboy> {
+    let mut gb = load_rom(args.cartridge(), args, config)?;
+    if let Some(path) = &args.logfile {
+        if path.display().to_string() == "-" {
+            gb.set_logfile(Box::new(std::io::stdout()));
+        } else {
+            gb.set_logfile(Box::new(File::create(path)?));
+        }
+    }
+    if let Some(addr) = &args.link_listen {
+        gb.connect_serial(Box::new(BgbLink::listen(addr)?));
+    } else if let Some(addr) = &args.link_connect {
+        gb.connect_serial(Box::new(BgbLink::connect(addr)?));
+    } else if args.printer {
+        let name = args.cartridge().file_stem().unwrap_or_default();
+        let printer = Printer::new(&config.prints_dir, &name.to_string_lossy());
+        gb.connect_serial(Box::new(printer));
+    }
+    Ok(gb)
+}
+
+fn load_rom(path: &Path, args: &Args, config: &Config) -> Result<Gameboy> {
+    let rom = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let bootrom = if args.skip_bootrom {
+        None
+    } else {
+        let path = match Model::from_rom(&rom) {
+            Model::Dmg => &config.bootrom,
+            Model::Cgb => &config.cgb_bootrom,
+        };
+        Some(std::fs::read(path)?)
+    };
+    Gameboy::new(rom, bootrom).with_context(|| format!("Failed to load {}", path.display()))
+}
@@ -0,0 +1,287 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use rgb::{SCREEN_WIDTH, SerialDevice};
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0f;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+const STATUS_PACKET_ERROR: u8 = 0x10;
+
+// The printer holds up to 8 KiB of image data, 20 tiles (160 pixels) wide
+const BUFFER_SIZE: usize = 0x2000;
+const TILES_PER_ROW: usize = SCREEN_WIDTH / 8;
+const TILE_ROW_BYTES: usize = TILES_PER_ROW * 16;
+// How long printing takes per 16 pixel high strip, reported through the
+// status byte. Games wait for it to finish.
+const PRINT_MCYCLES_PER_STRIP: u32 = 1 << 18;
+
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+#[derive(Copy, Clone)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    // The two bytes after a packet are where the printer answers
+    Alive,
+    Status,
+}
+
+// A Game Boy Printer on the link port. The Game Boy sends it packets of
+//   88 33 <command> <compression> <length:16> <data> <checksum:16> 00 00
+// and it answers the last two bytes with 81 (its ID) and its status. Each
+// print job (up to a print with a bottom margin, which feeds the paper out)
+// is saved as a PNG.
+pub struct Printer {
+    dir: PathBuf,
+    name: String,
+
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    status: u8,
+    buffer: Vec<u8>,
+    printing: u32,
+    // Grayscale rows printed so far in the current job
+    job: Vec<[u8; SCREEN_WIDTH]>,
+}
+
+impl Printer {
+    // Prints are named after the game, `name_1.png` and so on
+    pub fn new(dir: &Path, name: &str) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            buffer: Vec::new(),
+            printing: 0,
+            job: Vec::new(),
+        }
+    }
+
+    // Returns the reply to `byte`
+    fn receive(&mut self, byte: u8) -> u8 {
+        let (next, reply) = match self.state {
+            State::Magic1 => (
+                if byte == 0x88 {
+                    State::Magic2
+                } else {
+                    State::Magic1
+                },
+                0x00,
+            ),
+            State::Magic2 if byte == 0x33 => (State::Command, 0x00),
+            State::Magic2 => (State::Magic1, 0x00),
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                (State::Compression, 0x00)
+            }
+            State::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                (State::LengthLow, 0x00)
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                (State::LengthHigh, 0x00)
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 {
+                    (State::ChecksumLow, 0x00)
+                } else {
+                    (State::Data, 0x00)
+                }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize {
+                    (State::ChecksumLow, 0x00)
+                } else {
+                    (State::Data, 0x00)
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                (State::ChecksumHigh, 0x00)
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                if self.received_checksum == self.checksum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                (State::Alive, 0x00)
+            }
+            State::Alive => (State::Status, 0x81),
+            State::Status => {
+                let busy = if self.printing > 0 {
+                    STATUS_PRINTING
+                } else {
+                    0
+                };
+                (State::Magic1, self.status | busy)
+            }
+        };
+        self.state = next;
+        reply
+    }
+
+    fn execute(&mut self) {
+        self.status &= !STATUS_PACKET_ERROR;
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            DATA if self.packet.is_empty() => self.status |= STATUS_IMAGE_FULL,
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    std::mem::take(&mut self.packet)
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                self.status |= STATUS_UNPROCESSED;
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            PRINT if self.packet.len() == 4 => {
+                let margins = self.packet[1];
+                let palette = self.packet[2];
+                self.print(palette);
+                // A bottom margin feeds the paper out, ending the job
+                if margins & 0x0f != 0 {
+                    self.finish_job();
+                }
+            }
+            STATUS => {}
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        // Some games send a palette of 0, which prints like the usual E4
+        let palette = if palette == 0 { 0xe4 } else { palette };
+        let tile_rows = self.buffer.len() / TILE_ROW_BYTES;
+        for tile_row in self.buffer.chunks_exact(TILE_ROW_BYTES) {
+            for y in 0..8 {
+                let mut row = [0; SCREEN_WIDTH];
+                for (tile, pixels) in tile_row.chunks_exact(16).zip(row.chunks_exact_mut(8)) {
+                    let (lo, hi) = (tile[2 * y], tile[2 * y + 1]);
+                    for (x, pixel) in pixels.iter_mut().enumerate() {
+                        let bit = 7 - x;
+                        let color = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                        *pixel = SHADES[((palette >> (2 * color)) & 3) as usize];
+                    }
+                }
+                self.job.push(row);
+            }
+        }
+        self.buffer.clear();
+        self.status &= !(STATUS_IMAGE_FULL | STATUS_UNPROCESSED);
+        self.printing = (tile_rows as u32).div_ceil(2).max(1) * PRINT_MCYCLES_PER_STRIP;
+    }
+
+    fn finish_job(&mut self) {
+        if self.job.is_empty() {
+            return;
+        }
+        match self.save_job() {
+            Ok(path) => println!("Printed to {}", path.display()),
+            Err(e) => println!("Failed to save print: {e:?}"),
+        }
+        self.job.clear();
+    }
+
+    fn save_job(&self) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = (1..)
+            .map(|i| self.dir.join(format!("{}_{i}.png", self.name)))
+            .find(|path| !path.exists())
+            .unwrap();
+        let file = BufWriter::new(File::create(&path)?);
+        let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, self.job.len() as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.job.as_flattened())?;
+        Ok(path)
+    }
+}
+
+// Runs of control byte n & 0x7f + 2 copies of the next byte when bit 7 is
+// set, otherwise n + 1 literal bytes
+fn decompress(packet: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut bytes = packet.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(byte) = bytes.next() else {
+                break;
+            };
+            data.extend(std::iter::repeat_n(byte, (control & 0x7f) as usize + 2));
+        } else {
+            data.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    data
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        Some(self.receive(byte))
+    }
+
+    // The printer never clocks transfers itself
+    fn poll_external(&mut self, _: u8) -> Option<u8> {
+        None
+    }
+
+    fn tick(&mut self, _: bool, _: bool) {
+        self.printing = self.printing.saturating_sub(1);
+    }
+}
+
+// Prints still on the paper when the emulator exits
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.finish_job();
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use rgb::Gameboy;
+
+// Ring buffer of compressed savestates, one per frame, bounded by the total
+// compressed size. The oldest snapshots are dropped to make room.
+//
+// Taking and compressing a 160 KiB savestate costs about 0.25 ms in a release
+// build, under 2% of a frame, so there's no need to snapshot less often than
+// every frame. Restoring one skips the backup copy `load_savestate` makes,
+// which saves about 40%. The ignored tests in tests/rewind.rs measure both.
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    size: usize,
+    capacity: usize,
+    rewinding: bool,
+}
+
+impl Rewind {
+    pub fn new(capacity_mb: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            size: 0,
+            capacity: capacity_mb * 1024 * 1024,
+            rewinding: false,
+        }
+    }
+
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding && self.capacity > 0;
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    // Called after every frame of normal play
+    pub fn push(&mut self, gb: &Gameboy) {
+        if self.capacity == 0 {
+            return;
+        }
+        let snapshot = lz4_flex::compress_prepend_size(&gb.savestate());
+        self.size += snapshot.len();
+        self.snapshots.push_back(snapshot);
+        while self.size > self.capacity {
+            let Some(oldest) = self.snapshots.pop_front() else {
+                break;
+            };
+            self.size -= oldest.len();
+        }
+    }
+
+    // Goes back one frame. Savestates don't include the framebuffer, so this
+    // restores the snapshot from two frames back and runs it for a frame to
+    // redraw the previous one. Stops at the oldest snapshot.
+    pub fn step_back(&mut self, gb: &mut Gameboy) -> Result<()> {
+        if self.snapshots.len() < 3 {
+            return Ok(());
+        }
+        let newest = self.snapshots.pop_back().unwrap();
+        self.size -= newest.len();
+        let snapshot = &self.snapshots[self.snapshots.len() - 2];
+        gb.load_snapshot(&lz4_flex::decompress_size_prepended(snapshot)?)?;
+        gb.run_frame()?;
+        // Playing the sound backwards isn't worth it
+        gb.audio_samples().for_each(drop);
+        Ok(())
+    }
+}
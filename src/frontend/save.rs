@@ -0,0 +1,101 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rgb::Gameboy;
+
+const NUM_BACKUPS: u32 = 3;
+
+// Writes to a temporary file that's then renamed over the destination, so a
+// crash halfway through never leaves a truncated file behind
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+// The battery-backed RAM of the running game
+pub struct SaveFile {
+    path: PathBuf,
+    autosave_interval: Option<Duration>,
+    last_save: Instant,
+    backed_up: bool,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf, autosave_secs: u64) -> Self {
+        Self {
+            path,
+            autosave_interval: (autosave_secs > 0).then(|| Duration::from_secs(autosave_secs)),
+            last_save: Instant::now(),
+            backed_up: false,
+        }
+    }
+
+    pub fn load(&self, gb: &mut Gameboy) -> Result<()> {
+        if let Ok(mut file) = File::open(&self.path) {
+            gb.load_external_ram(&mut file)?;
+        }
+        Ok(())
+    }
+
+    // Called after every frame. Saves when the game is done writing to RAM,
+    // or periodically if it never disables RAM.
+    pub fn autosave(&mut self, gb: &mut Gameboy) -> Result<()> {
+        let flush = gb.take_external_ram_flush();
+        let due = gb.external_ram_dirty()
+            && self
+                .autosave_interval
+                .is_some_and(|interval| self.last_save.elapsed() >= interval);
+        if flush || due {
+            self.save(gb)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self, gb: &mut Gameboy) -> Result<()> {
+        let mut ram = Vec::new();
+        gb.save_external_ram(&mut ram)?;
+        self.last_save = Instant::now();
+        if ram.is_empty() {
+            return Ok(());
+        }
+        if !self.backed_up {
+            self.rotate_backups()?;
+            self.backed_up = true;
+        }
+        write_atomic(&self.path, &ram)
+    }
+
+    // Before the first save of a session, the existing save becomes `.sav.1`,
+    // and older backups move up to `.sav.<NUM_BACKUPS>`
+    fn rotate_backups(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        for i in (1..NUM_BACKUPS).rev() {
+            let backup = self.backup_path(i);
+            if backup.exists() {
+                fs::rename(&backup, self.backup_path(i + 1))?;
+            }
+        }
+        fs::copy(&self.path, self.backup_path(1))?;
+        Ok(())
+    }
+
+    fn backup_path(&self, i: u32) -> PathBuf {
+        with_suffix(&self.path, &format!(".{i}"))
+    }
+}
@@ -1,87 +1,201 @@
-use anyhow::Result;
-use std::fs::File;
-use winit::application::ApplicationHandler;
-use winit::event::WindowEvent;
-use winit::event_loop::ActiveEventLoop;
-use winit::window::WindowId;
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{Result, bail};
 
 use crate::apu::Apu;
 use crate::bus::Cartridge;
-use crate::config::{Args, Config};
-use crate::cpu::Cpu;
-use crate::display::{Display, DisplayEvent};
-use crate::hotkeys::Hotkey;
+use crate::bus::joypad::JoypadButton;
+use crate::bus::serial::SerialDevice;
+use crate::bus::watchpoint::{WatchHit, Watchpoint};
+use crate::cpu::{Cpu, CpuRegisters};
+use crate::model::Model;
 
 pub struct Gameboy {
     cpu: Cpu,
-    display: Display,
+    // Set when the core panicked. Its state can't be trusted after that, so
+    // emulation refuses to continue, but external RAM can still be saved.
+    fault: Option<String>,
 }
 
 impl Gameboy {
-    pub fn new(args: Args, config: Config) -> Result<Self> {
-        let display = Display::new(config.keymap(), config.scale);
-        let bootrom = if args.skip_bootrom {
-            None
-        } else {
-            Some(
-                std::fs::read(config.bootrom)?
-                    .try_into()
-                    .expect("Bootrom not 0x100 in length"),
-            )
-        };
-        let mut cartridge = Cartridge::new(args.cartridge, config.saves_dir)?;
-        cartridge.load_external_ram()?;
-        let logfile = args
-            .logfile
-            .map(|path| {
-                if path.display().to_string() == "-" {
-                    Ok(Box::new(std::io::stdout()) as Box<_>)
-                } else {
-                    File::create(path).map(|file| Box::new(file) as Box<_>)
-                }
-            })
-            .transpose()?;
-        let apu = Apu::new(config.audio_volume, args.disable_audio);
-        let cpu = Cpu::new(bootrom, cartridge, apu, logfile);
-        Ok(Self { cpu, display })
+    // The hardware model is picked from the cartridge header, and the bootrom
+    // (if any) must be the one for that model.
+    pub fn new(rom: Vec<u8>, bootrom: Option<Vec<u8>>) -> Result<Self> {
+        let model = Model::from_rom(&rom);
+        if let Some(bootrom) = &bootrom
+            && bootrom.len() != model.bootrom_size()
+        {
+            bail!(
+                "{model:?} bootrom must be {:#x} bytes, got {:#x}",
+                model.bootrom_size(),
+                bootrom.len()
+            );
+        }
+        let cartridge = Cartridge::new(rom)?;
+        let cpu = Cpu::new(model, bootrom, cartridge, Apu::new());
+        Ok(Self { cpu, fault: None })
     }
-}
 
-impl ApplicationHandler for Gameboy {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        if let Err(e) = self.display.reinit_surface(event_loop) {
-            println!("{e:?}");
-            self.display.quit(event_loop);
-        }
+    pub fn model(&self) -> Model {
+        self.cpu.model()
+    }
+
+    pub fn registers(&self) -> CpuRegisters {
+        self.cpu.registers()
+    }
+
+    pub fn set_registers(&mut self, registers: CpuRegisters) {
+        self.cpu.set_registers(registers);
+    }
+
+    // Reads memory as the CPU would see it, without side effects
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.cpu.read_memory(addr)
+    }
+
+    // ROM bank currently mapped at `addr` (0x0000-0x7fff)
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.cpu.rom_bank(addr)
+    }
+
+    // Text and length of the instruction at `addr`
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        self.cpu.disassemble(addr)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.cpu.memory().watchpoints()
     }
 
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
-        if let Some(display_event) = self.display.process_event(&event) {
-            match display_event {
-                DisplayEvent::RedrawRequested => {
-                    if let Err(e) = self.display.draw_frame(&mut self.cpu) {
-                        println!("{e:?}");
-                        self.display.quit(event_loop);
-                    }
-                }
-                DisplayEvent::Hotkey((hotkey, pressed)) => match hotkey {
-                    Hotkey::Joypad(button) => {
-                        self.cpu.joypad_mut().update_button(button, pressed);
-                    }
-                    Hotkey::ToggleFrameLimiter => {
-                        if pressed {
-                            self.display.toggle_frame_limiter();
-                            self.cpu.toggle_frame_limiter();
-                        }
-                    }
-                },
-                DisplayEvent::Quit => {
-                    if let Err(e) = self.cpu.save_external_ram() {
-                        println!("Failed to save: {e:?}");
-                    }
-                    self.display.quit(event_loop);
-                }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.cpu.memory_mut().add_watchpoint(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        self.cpu.memory_mut().remove_watchpoint(index)
+    }
+
+    // Watchpoint hits since the last call, in the order they happened
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.cpu.memory_mut().take_watch_hits()
+    }
+
+    // Whether a LD B,B software breakpoint was executed since the last call
+    pub fn take_breakpoint(&mut self) -> bool {
+        self.cpu.take_breakpoint()
+    }
+
+    pub fn set_logfile(&mut self, logfile: Box<dyn Write>) {
+        self.cpu.set_logfile(logfile);
+    }
+
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.catch_fault(Cpu::run_frame)
+    }
+
+    // Executes a single instruction (or M-cycle while halted). Returns whether
+    // a frame was completed.
+    pub fn step(&mut self) -> Result<bool> {
+        self.catch_fault(|cpu| {
+            cpu.step()?;
+            Ok(cpu.ppu_mut().draw_check())
+        })
+    }
+
+    // Turns a panic in the core into an error
+    fn catch_fault<T>(&mut self, f: impl FnOnce(&mut Cpu) -> Result<T>) -> Result<T> {
+        if let Some(fault) = &self.fault {
+            bail!("Emulation stopped after a fault: {fault}");
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut self.cpu))) {
+            Ok(result) => result,
+            Err(payload) => {
+                let fault = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                self.fault = Some(fault.clone());
+                bail!("Emulation fault: {fault}")
             }
         }
     }
+
+    // M-cycles run since power on
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    // Whether the CPU locked up after an illegal opcode (or a STOP glitch)
+    pub fn locked_up(&self) -> bool {
+        self.cpu.locked_up()
+    }
+
+    // RGBA8 pixels of the last completed frame, row by row
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.ppu().framebuffer()
+    }
+
+    // Shade (0-3, after palette mapping) of each pixel of the last completed
+    // frame. CGB palettes are arbitrary colors, so there it's the color index
+    // before palette lookup instead.
+    pub fn framebuffer_shades(&self) -> &[u8] {
+        self.cpu.ppu().shades()
+    }
+
+    // Stereo samples at SAMPLE_RATE produced since the last call
+    pub fn audio_samples(&mut self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.cpu.apu_mut().drain_samples()
+    }
+
+    pub fn update_button(&mut self, button: JoypadButton, pressed: bool) {
+        self.cpu.joypad_mut().update_button(button, pressed);
+    }
+
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.serial_mut().connect(device);
+    }
+
+    // Writes nothing for cartridges without a battery
+    pub fn save_external_ram(&mut self, writer: &mut dyn Write) -> Result<()> {
+        self.cpu.save_external_ram(writer)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.cpu.memory().cartridge.has_battery()
+    }
+
+    // Whether external RAM changed since the last save
+    pub fn external_ram_dirty(&self) -> bool {
+        self.cpu.memory().cartridge.ram_dirty()
+    }
+
+    // Whether the game wrote to external RAM and then disabled it since the
+    // last call, which games do when they finish saving
+    pub fn take_external_ram_flush(&mut self) -> bool {
+        self.cpu.memory_mut().cartridge.take_flush_request()
+    }
+
+    pub fn load_external_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.cpu.load_external_ram(reader)
+    }
+
+    pub fn savestate(&self) -> Vec<u8> {
+        self.cpu.savestate()
+    }
+
+    // Also recovers from a fault, since the whole state is replaced
+    pub fn load_savestate(&mut self, state: &[u8]) -> Result<()> {
+        self.cpu.load_savestate(state)?;
+        self.fault = None;
+        Ok(())
+    }
+
+    // A faster `load_savestate` for states from `savestate` in this session
+    pub fn load_snapshot(&mut self, state: &[u8]) -> Result<()> {
+        self.cpu.load_snapshot(state)?;
+        self.fault = None;
+        Ok(())
+    }
 }
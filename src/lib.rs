@@ -0,0 +1,21 @@
+mod apu;
+mod bus;
+mod cpu;
+mod gb;
+mod link;
+mod model;
+mod ppu;
+mod savestate;
+mod utils;
+
+pub use apu::SAMPLE_RATE;
+pub use bus::flat::FlatBus;
+pub use bus::joypad::JoypadButton;
+pub use bus::serial::SerialDevice;
+pub use bus::watchpoint::{Access, WatchCondition, WatchHit, Watchpoint};
+pub use bus::{Bus, CartridgeError, CartridgeHeader, CgbSupport, Stop};
+pub use cpu::{Cpu, CpuRegisters};
+pub use gb::Gameboy;
+pub use link::{FourPlayerAdapter, LinkedGameboys};
+pub use model::Model;
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
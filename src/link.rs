@@ -0,0 +1,442 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use anyhow::{Result, ensure};
+use generator::{Generator, Gn};
+
+use crate::bus::serial::SerialDevice;
+use crate::gb::Gameboy;
+
+// Coroutine stack size, in words. A step of the core runs on it.
+const STACK_SIZE: usize = 0x10000;
+// How long the two may run on past the end of a frame looking for a point in
+// time where both are between instructions, in halves of an M-cycle
+const ALIGN_WINDOW: u64 = 1024;
+
+// Two Game Boys in one process with their link ports wired together. They're
+// run in lockstep an M-cycle at a time: each runs on a coroutine that its link
+// port yields from every M-cycle, and whichever is behind in time runs next,
+// side 0 first on a tie. Each sees the other's end of the cable as it is at
+// that M-cycle, double speed included, and linked runs are fully
+// deterministic.
+//
+// Between frames both have to be stopped between instructions. They run on
+// until they are at the same point in time, or for ALIGN_WINDOW at most, after
+// which each stops at its next instruction boundary. Only then can one be a
+// few M-cycles ahead, and see the other's link port that much early while the
+// other catches up at the start of the next frame.
+pub struct LinkedGameboys {
+    gameboys: [Gameboy; 2],
+    wire: Rc<RefCell<Wire>>,
+}
+
+impl LinkedGameboys {
+    pub fn new(mut gameboys: [Gameboy; 2]) -> Self {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        for (side, gb) in gameboys.iter_mut().enumerate() {
+            gb.connect_serial(Box::new(LinkPort {
+                wire: wire.clone(),
+                side,
+            }));
+        }
+        Self { gameboys, wire }
+    }
+
+    pub fn gameboys(&self) -> &[Gameboy; 2] {
+        &self.gameboys
+    }
+
+    pub fn gameboys_mut(&mut self) -> &mut [Gameboy; 2] {
+        &mut self.gameboys
+    }
+
+    // Runs until both have completed a frame
+    pub fn run_frame(&mut self) -> Result<()> {
+        let done = [Cell::new(false), Cell::new(false)];
+        // Waiting between instructions for the other to get there too
+        let parked = [Cell::new(false), Cell::new(false)];
+        let stop = Cell::new(false);
+        let results = [Cell::new(None), Cell::new(None)];
+        let mut sides: Vec<Generator<(), ()>> = self
+            .gameboys
+            .iter_mut()
+            .enumerate()
+            .map(|(side, gb)| {
+                let (done, parked, stop) = (&done, &parked, &stop);
+                let mut run = move || -> Result<()> {
+                    loop {
+                        if done[0].get() && done[1].get() {
+                            parked[side].set(true);
+                            yield_to_run_frame();
+                        }
+                        if stop.get() {
+                            return Ok(());
+                        }
+                        if gb.step()? {
+                            done[side].set(true);
+                        }
+                    }
+                };
+                let result = &results[side];
+                let run = ThisThread(move || result.set(Some(run())));
+                Gn::new_opt(STACK_SIZE, move || run.call())
+            })
+            .collect();
+
+        let mut deadline = None;
+        while sides.iter().any(|side| !side.is_done()) {
+            let time = self.wire.borrow().time;
+            if parked[0].get() && parked[1].get() {
+                let deadline = *deadline.get_or_insert(time[0].max(time[1]) + ALIGN_WINDOW);
+                if time[0] == time[1] || time[0].min(time[1]) >= deadline {
+                    stop.set(true);
+                }
+            }
+            // Whichever is behind, preferring one that's still running so
+            // that a parked one is only woken if the other got past it
+            let side = (0..2)
+                .filter(|&side| !sides[side].is_done())
+                .min_by_key(|&side| (time[side], parked[side].get()))
+                .unwrap();
+            parked[side].set(false);
+            sides[side].resume();
+            if sides[side].is_done() {
+                // Let the other stop at its next instruction boundary
+                stop.set(true);
+            }
+        }
+        drop(sides);
+        results.into_iter().flat_map(Cell::into_inner).collect()
+    }
+}
+
+// Suspends the coroutine a side of `LinkedGameboys` runs on. The scoped
+// version of this needs the generator's scope, which would have to be passed
+// down through the core.
+#[allow(deprecated)]
+fn yield_to_run_frame() {
+    generator::yield_with(());
+}
+
+// Coroutines have to be Send to be suspended with `yield_to_run_frame`, but
+// the ones in `LinkedGameboys::run_frame` borrow both Game Boys, which aren't.
+// They never leave the thread: each is created, resumed until done and
+// dropped within the call.
+struct ThisThread<F>(F);
+
+unsafe impl<F> Send for ThisThread<F> {}
+
+impl<F: FnOnce()> ThisThread<F> {
+    fn call(self) {
+        (self.0)()
+    }
+}
+
+// Both ends of the cable, indexed by side
+#[derive(Default)]
+struct Wire {
+    // The byte each side shifts out, while it waits for the other to clock a
+    // transfer
+    passive: [Option<u8>; 2],
+    // Bytes clocked in by the other side, not yet received
+    incoming: [Option<u8>; 2],
+    // How far each side has run, in halves of a normal speed M-cycle
+    time: [u64; 2],
+}
+
+struct LinkPort {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl SerialDevice for LinkPort {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+        // Nothing shifts back if the other side isn't waiting for a transfer
+        match wire.passive[other].take() {
+            Some(reply) => {
+                wire.incoming[other] = Some(byte);
+                Some(reply)
+            }
+            None => Some(0xff),
+        }
+    }
+
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let incoming = wire.incoming[self.side].take();
+        wire.passive[self.side] = incoming.is_none().then_some(byte);
+        incoming
+    }
+
+    fn tick(&mut self, passive: bool, double_speed: bool) {
+        {
+            let mut wire = self.wire.borrow_mut();
+            if !passive {
+                wire.passive[self.side] = None;
+                wire.incoming[self.side] = None;
+            }
+            wire.time[self.side] += if double_speed { 1 } else { 2 };
+        }
+        // Hand over to `LinkedGameboys::run_frame`, unless stepped outside it
+        if generator::is_generator() {
+            yield_to_run_frame();
+        }
+    }
+}
+
+// How often the adapter clocks a byte, in M-cycles. Pings go out about every
+// 2ms. Transmission runs faster, slowed down by the low nibble of the RATE
+// player 1 asked for. These are estimates, not measured on a real adapter or
+// taken from documented timings: they only keep the bytes far enough apart
+// for a game's serial interrupt handler to store one before the next arrives.
+const PING_PERIOD: u64 = 2048;
+const TRANSMISSION_PERIOD: u64 = 512;
+const RATE_STEP: u64 = 128;
+
+const PING_HEADER: u8 = 0xfe;
+const ACK: u8 = 0x88;
+// Sent by player 1 to start the transmission phase, which the adapter
+// confirms with four of these
+const START: u8 = 0xaa;
+const START_CONFIRM: u8 = 0xcc;
+// Four bytes of this from every player return to the ping phase
+const RESTART: u8 = 0xff;
+
+// The DMG-07 four player adapter, with up to four Game Boys plugged in. The
+// adapter clocks every transfer, and the Game Boys are interleaved an
+// instruction at a time, always running whichever is behind in M-cycles.
+pub struct FourPlayerAdapter {
+    gameboys: Vec<Gameboy>,
+    lines: Vec<Rc<RefCell<Line>>>,
+    adapter: Dmg07,
+    next_clock: u64,
+}
+
+impl FourPlayerAdapter {
+    pub fn new(mut gameboys: Vec<Gameboy>) -> Result<Self> {
+        ensure!(
+            (1..=4).contains(&gameboys.len()),
+            "The four player adapter takes 1 to 4 Game Boys, not {}",
+            gameboys.len()
+        );
+        let lines: Vec<_> = gameboys
+            .iter_mut()
+            .map(|gb| {
+                let line = Rc::new(RefCell::new(Line::default()));
+                gb.connect_serial(Box::new(AdapterPort { line: line.clone() }));
+                line
+            })
+            .collect();
+        Ok(Self {
+            gameboys,
+            lines,
+            adapter: Dmg07::default(),
+            next_clock: PING_PERIOD,
+        })
+    }
+
+    pub fn gameboys(&self) -> &[Gameboy] {
+        &self.gameboys
+    }
+
+    pub fn gameboys_mut(&mut self) -> &mut [Gameboy] {
+        &mut self.gameboys
+    }
+
+    // Runs until all have completed a frame
+    pub fn run_frame(&mut self) -> Result<()> {
+        let mut done = vec![false; self.gameboys.len()];
+        while done.contains(&false) {
+            let (player, gb) = self
+                .gameboys
+                .iter_mut()
+                .enumerate()
+                .min_by_key(|(_, gb)| gb.cycles())
+                .unwrap();
+            done[player] |= gb.step()?;
+            while self
+                .gameboys
+                .iter()
+                .all(|gb| gb.cycles() >= self.next_clock)
+            {
+                self.clock();
+            }
+        }
+        Ok(())
+    }
+
+    // Shifts a byte in and out of every port at once. Game Boys that aren't
+    // waiting for a transfer miss it, and send the adapter 0xff.
+    fn clock(&mut self) {
+        let mut replies = [RESTART; 4];
+        for (player, line) in self.lines.iter().enumerate() {
+            let mut line = line.borrow_mut();
+            if let Some(reply) = line.ready.take() {
+                line.incoming = Some(self.adapter.output(player));
+                replies[player] = reply;
+            }
+        }
+        self.adapter.receive(replies);
+        self.next_clock += self.adapter.period();
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+enum Phase {
+    // Pings tell every Game Boy its player number and who's connected, and
+    // the replies acknowledge them, with player 1 also picking the rate and
+    // packet size
+    #[default]
+    Ping,
+    Starting,
+    // Every cycle of 4 * SIZE bytes, each player sends its SIZE bytes first,
+    // while receiving everyone's bytes from the cycle before
+    Transmission,
+}
+
+#[derive(Default)]
+struct Dmg07 {
+    phase: Phase,
+    // Byte within the current ping packet, confirmation or cycle
+    position: usize,
+    connected: [bool; 4],
+    acks: [bool; 4],
+    rate: u8,
+    size: u8,
+    start: bool,
+    packet: Vec<u8>,
+    next_packet: Vec<u8>,
+    restarts: [u8; 4],
+}
+
+impl Dmg07 {
+    fn size(&self) -> usize {
+        self.size.max(1) as usize
+    }
+
+    fn period(&self) -> u64 {
+        match self.phase {
+            Phase::Ping => PING_PERIOD,
+            _ => TRANSMISSION_PERIOD + (self.rate & 0x0f) as u64 * RATE_STEP,
+        }
+    }
+
+    fn output(&self, player: usize) -> u8 {
+        match self.phase {
+            Phase::Ping if self.position == 0 => PING_HEADER,
+            Phase::Ping => {
+                let connected = (0..4)
+                    .filter(|&i| self.connected[i])
+                    .fold(0, |mask, i| mask | 0x10 << i);
+                connected | (player as u8 + 1)
+            }
+            Phase::Starting => START_CONFIRM,
+            Phase::Transmission => self.packet[self.position],
+        }
+    }
+
+    fn receive(&mut self, replies: [u8; 4]) {
+        self.position += 1;
+        match self.phase {
+            Phase::Ping => {
+                let position = self.position - 1;
+                for (ack, &reply) in self.acks.iter_mut().zip(&replies) {
+                    match position {
+                        0 => *ack = reply == ACK,
+                        1 => *ack &= reply == ACK,
+                        _ => {}
+                    }
+                }
+                // Only from pings player 1 acknowledged, the one with 0xaa
+                // doesn't count
+                match position {
+                    2 if self.acks[0] => self.rate = replies[0],
+                    3 if self.acks[0] => self.size = replies[0],
+                    _ => {}
+                }
+                self.start |= replies[0] == START;
+                // The next ping shows who acknowledged this one
+                if self.position == 4 {
+                    self.position = 0;
+                    for (connected, ack) in self.connected.iter_mut().zip(self.acks) {
+                        *connected |= ack;
+                    }
+                    if std::mem::take(&mut self.start) {
+                        self.phase = Phase::Starting;
+                    }
+                }
+            }
+            Phase::Starting => {
+                if self.position == 4 {
+                    self.position = 0;
+                    self.phase = Phase::Transmission;
+                    self.packet = vec![0; 4 * self.size()];
+                    self.next_packet = vec![0; 4 * self.size()];
+                    self.restarts = [0; 4];
+                }
+            }
+            Phase::Transmission => {
+                let (size, position) = (self.size(), self.position - 1);
+                for (player, &reply) in replies.iter().enumerate() {
+                    if position < size && self.connected[player] {
+                        self.next_packet[player * size + position] = reply;
+                    }
+                    self.restarts[player] = match reply {
+                        RESTART => self.restarts[player].saturating_add(1),
+                        _ => 0,
+                    };
+                }
+                if self.position == self.packet.len() {
+                    self.position = 0;
+                    std::mem::swap(&mut self.packet, &mut self.next_packet);
+                    self.next_packet.fill(0);
+                }
+                // Ports with nothing plugged in never connect
+                let restart = (0..4)
+                    .filter(|&i| self.connected[i])
+                    .all(|i| self.restarts[i] >= 4);
+                if restart {
+                    *self = Self::default();
+                }
+            }
+        }
+    }
+}
+
+// One port of the adapter
+#[derive(Default)]
+struct Line {
+    // The byte the Game Boy shifts out, while it waits for the adapter to
+    // clock a transfer
+    ready: Option<u8>,
+    // The byte clocked in by the adapter, not yet received
+    incoming: Option<u8>,
+}
+
+struct AdapterPort {
+    line: Rc<RefCell<Line>>,
+}
+
+impl SerialDevice for AdapterPort {
+    // The adapter clocks everything, nothing answers a Game Boy that tries to
+    fn transfer(&mut self, _: u8) -> Option<u8> {
+        Some(0xff)
+    }
+
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        let mut line = self.line.borrow_mut();
+        let incoming = line.incoming.take();
+        line.ready = incoming.is_none().then_some(byte);
+        incoming
+    }
+
+    fn tick(&mut self, passive: bool, _: bool) {
+        if !passive {
+            let mut line = self.line.borrow_mut();
+            line.ready = None;
+            line.incoming = None;
+        }
+    }
+}
@@ -1,15 +1,7 @@
-mod apu;
-mod bus;
-mod config;
-mod cpu;
-mod display;
-mod gb;
-mod hotkeys;
-mod ppu;
-mod utils;
+mod frontend;
 
-use config::{Args, Config};
-use gb::Gameboy;
+use frontend::{Args, Command, Config};
+#[cfg(feature = "frontend")]
 use winit::event_loop::EventLoop;
 
 use anyhow::Result;
@@ -17,9 +9,26 @@ use clap::Parser;
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    frontend::logger::init();
+    if let Some(Command::Info { roms }) = &args.command {
+        return frontend::info::run(roms);
+    }
     let config = Config::new(args.config.as_ref())?;
-    let mut gb = Gameboy::new(args, config)?;
+    if args.headless {
+        return frontend::headless::run(args, config);
+    }
+    run_window(args, config)
+}
+
+#[cfg(feature = "frontend")]
+fn run_window(args: Args, config: Config) -> Result<()> {
+    let mut app = frontend::App::new(args, config)?;
     let event_loop = EventLoop::new()?;
-    event_loop.run_app(&mut gb)?;
+    event_loop.run_app(&mut app)?;
     Ok(())
 }
+
+#[cfg(not(feature = "frontend"))]
+fn run_window(_: Args, _: Config) -> Result<()> {
+    anyhow::bail!("Built without the `frontend` feature, so only --headless is available")
+}
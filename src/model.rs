@@ -0,0 +1,27 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Cgb,
+}
+
+impl Model {
+    // Header byte 0x143 has bit 7 set for games that support CGB features,
+    // both 0x80 (CGB enhanced) and 0xc0 (CGB only).
+    pub fn from_rom(rom: &[u8]) -> Self {
+        match rom.get(0x143) {
+            Some(flag) if flag & 0x80 != 0 => Model::Cgb,
+            _ => Model::Dmg,
+        }
+    }
+
+    pub fn bootrom_size(self) -> usize {
+        match self {
+            Model::Dmg => 0x100,
+            Model::Cgb => 0x900,
+        }
+    }
+
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb
+    }
+}
@@ -1,6 +1,11 @@
+use crate::model::Model;
+use crate::savestate::{Savestate, impl_savestate, take};
 use crate::utils::BitExtract;
-use anyhow::Result;
-use pixels::Pixels;
+use anyhow::{Result, bail};
+use std::collections::VecDeque;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
 const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
 const LIGHT_GRAY: [u8; 4] = [0xaa, 0xaa, 0xaa, 0xff];
@@ -9,7 +14,8 @@ const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
 
 #[allow(non_snake_case)]
 pub struct Ppu {
-    vram: Box<[u8; 0x2000]>,
+    model: Model,
+    vram: Box<[u8; 0x4000]>,
     oam_ram: Box<[u8; 0xA0]>,
     LCDC: u8,
     STAT: u8,
@@ -23,19 +29,54 @@ pub struct Ppu {
     WY: u8,
     WX: u8,
     WC: u8,
+    VBK: u8,
+    BCPS: u8,
+    OCPS: u8,
+    OPRI: u8,
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
 
     mode: PpuMode,
+    // CPU access to OAM is blocked during modes 2 and 3, and to VRAM (and CGB
+    // palette RAM) during mode 3
+    oam_blocked: bool,
+    vram_blocked: bool,
     stat_condition: bool,
+    // Set by the DMG STAT write quirk, reported on the next step
+    stat_write_irq: bool,
     viewport: Box<[[Pixel; 160]; 144]>,
+    framebuffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]>,
+    shades: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
     oam_sprites: Vec<Sprite>,
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<FifoPixel>,
+    sprite_fifo: VecDeque<FifoPixel>,
+    sprite_fetch: Option<Sprite>,
+    sprite_dots: u8,
+    lx: u8,
+    discard: u8,
+    delay: u8,
+    wy_triggered: bool,
     cycles: u16,
     ticks: u16,
     pub draw: bool,
+    hblank: bool,
 
     first_lcd_frame: bool,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OamCorruption {
+    Read,
+    Write,
+    // A read and a 16-bit increment or decrement of the same address in one
+    // M-cycle, like POP or LD A,(HL+)
+    ReadDuringIncrease,
+}
+
+#[derive(Default)]
 struct Sprite {
+    index: u8,
     tile: u8,
     x: u8,
     y: u8,
@@ -43,11 +84,27 @@ struct Sprite {
     x_flip: bool,
     y_flip: bool,
     palette: bool,
+    bank: bool,
+    cgb_palette: u8,
 }
 
+impl_savestate!(Sprite {
+    index,
+    tile,
+    x,
+    y,
+    priority,
+    x_flip,
+    y_flip,
+    palette,
+    bank,
+    cgb_palette,
+});
+
 impl Sprite {
-    fn from_oam_data(data: [u8; 4]) -> Self {
+    fn from_oam_data(index: u8, data: [u8; 4]) -> Self {
         Self {
+            index,
             tile: data[2],
             x: data[1],
             y: data[0],
@@ -55,28 +112,83 @@ impl Sprite {
             x_flip: data[3].bit(5),
             y_flip: data[3].bit(6),
             palette: data[3].bit(4),
+            bank: data[3].bit(3),
+            cgb_palette: data[3] & 0b111,
         }
     }
 }
 
 #[derive(Copy, Clone, Default)]
 struct Pixel {
+    shade: u8,
+    color: [u8; 4],
+}
+
+impl_savestate!(Pixel { shade, color });
+
+// A pixel in one of the FIFOs. Palettes are only looked up when the pixel is
+// shifted out, so `palette` is just the palette number (OBP0/OBP1 for DMG
+// sprites).
+#[derive(Copy, Clone, Default)]
+struct FifoPixel {
     color_idx: u8,
     palette: u8,
+    priority: bool,
+    oam_index: u8,
 }
 
-impl Pixel {
-    fn color(&self) -> [u8; 4] {
-        match (self.palette >> (2 * self.color_idx)) & 0b11 {
-            0 => WHITE,
-            1 => LIGHT_GRAY,
-            2 => DARK_GRAY,
-            3 => BLACK,
-            _ => unreachable!(),
-        }
+impl_savestate!(FifoPixel {
+    color_idx,
+    palette,
+    priority,
+    oam_index,
+});
+
+// Background/window tile fetcher. Each of the tile number, low and high data
+// byte fetches takes 2 dots, after which the fetcher waits for the BG FIFO to
+// empty before pushing 8 pixels.
+#[derive(Default)]
+struct Fetcher {
+    step: u8,
+    x: u8,
+    window: bool,
+    tile: u8,
+    attrs: u8,
+    lo: u8,
+    hi: u8,
+}
+
+impl_savestate!(Fetcher {
+    step,
+    x,
+    window,
+    tile,
+    attrs,
+    lo,
+    hi,
+});
+
+fn shade_color(shade: u8) -> [u8; 4] {
+    match shade {
+        0 => WHITE,
+        1 => LIGHT_GRAY,
+        2 => DARK_GRAY,
+        3 => BLACK,
+        _ => unreachable!(),
     }
 }
 
+// CGB palette RAM holds little-endian RGB555 colors
+fn cgb_color(palette_ram: &[u8; 64], palette: u8, color_idx: u8) -> [u8; 4] {
+    let idx = 8 * palette as usize + 2 * color_idx as usize;
+    let color = u16::from_le_bytes([palette_ram[idx], palette_ram[idx + 1]]);
+    let [r, g, b] = [0, 5, 10].map(|shift| {
+        let c = ((color >> shift) & 0x1f) as u8;
+        (c << 3) | (c >> 2)
+    });
+    [r, g, b, 0xff]
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
 enum PpuMode {
@@ -86,10 +198,72 @@ enum PpuMode {
     VBlank = 1,
 }
 
+impl Savestate for PpuMode {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        *self = match take(state)? {
+            [0] => PpuMode::HBlank,
+            [1] => PpuMode::VBlank,
+            [2] => PpuMode::OamScan,
+            [3] => PpuMode::Drawing,
+            [mode] => bail!("Invalid PPU mode in savestate: {mode}"),
+        };
+        Ok(())
+    }
+}
+
+impl_savestate!(Ppu {
+    vram,
+    oam_ram,
+    LCDC,
+    STAT,
+    SCY,
+    SCX,
+    LY,
+    LYC,
+    BGP,
+    OBP0,
+    OBP1,
+    WY,
+    WX,
+    WC,
+    VBK,
+    BCPS,
+    OCPS,
+    OPRI,
+    bg_palettes,
+    obj_palettes,
+    mode,
+    oam_blocked,
+    vram_blocked,
+    stat_condition,
+    stat_write_irq,
+    viewport,
+    oam_sprites,
+    fetcher,
+    bg_fifo,
+    sprite_fifo,
+    sprite_fetch,
+    sprite_dots,
+    lx,
+    discard,
+    delay,
+    wy_triggered,
+    cycles,
+    ticks,
+    draw,
+    hblank,
+    first_lcd_frame,
+});
+
 impl Ppu {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         Self {
-            vram: vec![0; 0x2000].try_into().unwrap(),
+            model,
+            vram: vec![0; 0x4000].try_into().unwrap(),
             oam_ram: vec![0; 0xA0].try_into().unwrap(),
             LCDC: 0,
             STAT: 0x80,
@@ -103,14 +277,38 @@ impl Ppu {
             WY: 0,
             WX: 0,
             WC: 0,
+            VBK: 0,
+            BCPS: 0,
+            OCPS: 0,
+            OPRI: 0,
+            // Palettes start out white when the bootrom is skipped
+            bg_palettes: [0xff; 64],
+            obj_palettes: [0xff; 64],
 
             mode: PpuMode::HBlank,
+            oam_blocked: false,
+            vram_blocked: false,
             stat_condition: false,
+            stat_write_irq: false,
             viewport: Box::new([[Pixel::default(); 160]; 144]),
+            framebuffer: vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
+                .try_into()
+                .unwrap(),
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT].try_into().unwrap(),
             oam_sprites: Vec::with_capacity(10),
+            fetcher: Fetcher::default(),
+            bg_fifo: VecDeque::with_capacity(16),
+            sprite_fifo: VecDeque::with_capacity(8),
+            sprite_fetch: None,
+            sprite_dots: 0,
+            lx: 0,
+            discard: 0,
+            delay: 0,
+            wy_triggered: false,
             cycles: 0,
             ticks: 0,
             draw: false,
+            hblank: false,
 
             first_lcd_frame: false,
         }
@@ -118,11 +316,8 @@ impl Ppu {
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x8000..=0x9fff => match self.mode {
-                // FIXME: Enable after implementing variable mode 3 length
-                // PpuMode::Drawing => 0xff,
-                _ => self.read_vram(addr),
-            },
+            0x8000..=0x9fff if self.vram_blocked => 0xff,
+            0x8000..=0x9fff => self.read_vram(addr),
             0xff40 => self.LCDC,
             0xff41 => self.STAT,
             0xff42 => self.SCY,
@@ -134,28 +329,58 @@ impl Ppu {
             0xff49 => self.OBP1,
             0xff4a => self.WY,
             0xff4b => self.WX,
-            _ => panic!("Invalid PPU Register read: {addr:04x}"),
+            0xff4f if self.model.is_cgb() => 0xfe | self.VBK,
+            0xff68 if self.model.is_cgb() => self.BCPS | 0x40,
+            0xff69 if self.model.is_cgb() => self.read_palette(self.BCPS, &self.bg_palettes),
+            0xff6a if self.model.is_cgb() => self.OCPS | 0x40,
+            0xff6b if self.model.is_cgb() => self.read_palette(self.OCPS, &self.obj_palettes),
+            0xff6c if self.model.is_cgb() => 0xfe | self.OPRI,
+            _ => {
+                log::warn!("Read from unknown PPU register {addr:04x}");
+                0xff
+            }
         }
     }
 
     pub fn read_oam(&self, slot: usize) -> u8 {
-        match self.mode {
-            PpuMode::OamScan | PpuMode::Drawing => 0xff,
-            _ => self.oam_ram[slot],
+        if self.oam_blocked {
+            0xff
+        } else {
+            self.oam_ram[slot]
         }
     }
 
     fn read_vram(&self, idx: u16) -> u8 {
-        self.vram[idx as usize - 0x8000]
+        self.vram_bank(self.VBK, idx)
+    }
+
+    fn vram_bank(&self, bank: u8, idx: u16) -> u8 {
+        self.vram[0x2000 * bank as usize + idx as usize - 0x8000]
+    }
+
+    // Palette RAM is inaccessible while the PPU is drawing
+    fn read_palette(&self, spec: u8, palettes: &[u8; 64]) -> u8 {
+        if self.vram_blocked {
+            0xff
+        } else {
+            palettes[(spec & 0x3f) as usize]
+        }
+    }
+
+    fn write_palette(spec: &mut u8, palettes: &mut [u8; 64], blocked: bool, val: u8) {
+        if !blocked {
+            palettes[(*spec & 0x3f) as usize] = val;
+        }
+        // Auto-increment happens even if the write itself was blocked
+        if spec.bit(7) {
+            *spec = 0x80 | (spec.wrapping_add(1) & 0x3f);
+        }
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0x8000..=0x9fff => match self.mode {
-                // FIXME: Enable after implementing variable mode 3 length
-                // PpuMode::Drawing => {}
-                _ => self.vram[addr as usize - 0x8000] = val,
-            },
+            0x8000..=0x9fff if self.vram_blocked => {}
+            0x8000..=0x9fff => self.vram[0x2000 * self.VBK as usize + addr as usize - 0x8000] = val,
             0xff40 => {
                 if val.bit(7) && !self.LCDC.bit(7) {
                     self.first_lcd_frame = true;
@@ -163,6 +388,16 @@ impl Ppu {
                 self.LCDC = val;
             }
             0xff41 => {
+                // On DMG, the write briefly enables every STAT source. If the
+                // HBlank, VBlank or LY=LYC condition holds and the line was
+                // low, that's a rising edge.
+                if !self.model.is_cgb()
+                    && self.LCDC.bit(7)
+                    && !self.stat_condition
+                    && (matches!(self.mode, PpuMode::HBlank | PpuMode::VBlank) || self.STAT.bit(2))
+                {
+                    self.stat_write_irq = true;
+                }
                 self.STAT &= 0b10000111; // Clear writeable bits
                 self.STAT |= val & 0b01111000; // Set those bits
             }
@@ -175,14 +410,25 @@ impl Ppu {
             0xff49 => self.OBP1 = val,
             0xff4a => self.WY = val,
             0xff4b => self.WX = val,
-            _ => panic!("Invalid PPU Register write: {addr:04x} = {val:#02x}"),
+            0xff4f if self.model.is_cgb() => self.VBK = val & 1,
+            0xff68 if self.model.is_cgb() => self.BCPS = val & 0xbf,
+            0xff69 if self.model.is_cgb() => {
+                let blocked = self.vram_blocked;
+                Self::write_palette(&mut self.BCPS, &mut self.bg_palettes, blocked, val)
+            }
+            0xff6a if self.model.is_cgb() => self.OCPS = val & 0xbf,
+            0xff6b if self.model.is_cgb() => {
+                let blocked = self.vram_blocked;
+                Self::write_palette(&mut self.OCPS, &mut self.obj_palettes, blocked, val)
+            }
+            0xff6c if self.model.is_cgb() => self.OPRI = val & 1,
+            _ => log::warn!("Write to unknown PPU register {addr:04x} = {val:02x}"),
         }
     }
 
     pub fn write_oam(&mut self, slot: u8, val: u8) {
-        match self.mode {
-            PpuMode::OamScan | PpuMode::Drawing => {}
-            _ => self.write_dma(slot, val),
+        if !self.oam_blocked {
+            self.write_dma(slot, val);
         }
     }
 
@@ -190,80 +436,148 @@ impl Ppu {
         self.oam_ram[oam_slot as usize] = val;
     }
 
+    // The DMG OAM corruption bug. Putting an OAM address on the bus during
+    // mode 2 garbles the 8-byte row the PPU is scanning in that M-cycle. The
+    // patterns are bitwise, so they're applied a byte at a time rather than
+    // on 16-bit words.
+    pub fn corrupt_oam(&mut self, corruption: OamCorruption) {
+        let Some(row) = self.oam_scan_row() else {
+            return;
+        };
+        match corruption {
+            OamCorruption::Write => self.corrupt_oam_row(row, |a, b, c| ((a ^ c) & (b ^ c)) ^ c),
+            OamCorruption::Read => self.corrupt_oam_row(row, |a, b, c| b | (a & c)),
+            OamCorruption::ReadDuringIncrease => {
+                // Rows 1-3 and the last one only get the normal read pattern
+                if (4..19).contains(&row) {
+                    let (two_back, prev, cur) = (8 * (row - 2), 8 * (row - 1), 8 * row);
+                    for i in 0..2 {
+                        let a = self.oam_ram[two_back + i];
+                        let b = self.oam_ram[prev + i];
+                        let c = self.oam_ram[cur + i];
+                        let d = self.oam_ram[prev + 4 + i];
+                        self.oam_ram[prev + i] = (b & (a | c | d)) | (a & c & d);
+                    }
+                    self.oam_ram.copy_within(prev..prev + 8, cur);
+                    self.oam_ram.copy_within(prev..prev + 8, two_back);
+                }
+                self.corrupt_oam_row(row, |a, b, c| b | (a & c));
+            }
+        }
+    }
+
+    // The first word of the row is mixed with the first and third words of
+    // the one before, and the rest is copied from it
+    fn corrupt_oam_row(&mut self, row: usize, first_word: impl Fn(u8, u8, u8) -> u8) {
+        let (prev, cur) = (8 * (row - 1), 8 * row);
+        for i in 0..2 {
+            let a = self.oam_ram[cur + i];
+            let b = self.oam_ram[prev + i];
+            let c = self.oam_ram[prev + 4 + i];
+            self.oam_ram[cur + i] = first_word(a, b, c);
+        }
+        self.oam_ram.copy_within(prev + 2..prev + 8, cur + 2);
+    }
+
+    // The row of OAM the scan reads in the upcoming M-cycle, one row (two
+    // entries) per M-cycle. Row 0 is never corrupted.
+    fn oam_scan_row(&self) -> Option<usize> {
+        let clocks = self.cycles % 114;
+        (!self.model.is_cgb()
+            && self.LCDC.bit(7)
+            && self.mode == PpuMode::OamScan
+            && (1..20).contains(&clocks))
+        .then_some(clocks as usize)
+    }
+
     pub fn step(&mut self) -> (bool, bool) {
         if self.ticks == 17556 {
             self.ticks = 0;
             self.draw = true;
+            self.update_framebuffer();
         }
 
         if self.LCDC.bit(7) {
             let (vblank, stat) = self.cycle();
             self.ticks = self.cycles + 1;
             self.cycles = (self.cycles + 1) % 17556;
-            (vblank, stat)
+            (vblank, stat || std::mem::take(&mut self.stat_write_irq))
         } else {
             // Hold everything to 0 while PPU is disabled
             self.cycles = 0;
             self.LY = 0;
             self.set_mode(PpuMode::HBlank);
+            self.oam_blocked = false;
+            self.vram_blocked = false;
             self.ticks += 1;
             (false, false)
         }
     }
 
+    // Keeps frames coming while the system clock is stopped
+    pub fn step_stopped(&mut self) {
+        if self.ticks == 17556 {
+            self.ticks = 0;
+            self.draw = true;
+        }
+        self.ticks += 1;
+    }
+
     fn cycle(&mut self) -> (bool, bool) {
         let mut vblank = false;
+        let mut stat = false;
 
         let clocks = self.cycles % 114;
         let scanline = self.cycles / 114;
 
-        if clocks == 0 {
-            self.LY = scanline as u8;
-            if scanline == 0 {
-                self.WC = 0;
-            }
-        }
-
-        if scanline < 144 {
-            if clocks == 0 {
-                self.oam_sprites.clear();
-                self.set_mode(PpuMode::OamScan);
-            } else if clocks == 20 {
-                self.set_mode(PpuMode::Drawing);
-                self.draw_line();
-            } else {
-                // TODO: Variable mode 3 length
-                if clocks == 63 {
-                    self.set_mode(PpuMode::HBlank);
+        for dot in 4 * clocks..4 * clocks + 4 {
+            if dot == 0 {
+                self.LY = scanline as u8;
+                if scanline == 0 {
+                    self.WC = 0;
+                    self.wy_triggered = false;
                 }
             }
 
-            // OAM scan
-            if clocks < 20 {
-                // Fetch two sprites per cycle
-                let oam_index = 2 * clocks as usize;
-                for i in oam_index..oam_index + 2 {
-                    if let Some(sprite) = self.fetch_sprite(i)
-                        && self.oam_sprites.len() < 10
-                    {
-                        let idx = self
-                            .oam_sprites
-                            .binary_search_by(|s| sprite.x.cmp(&s.x))
-                            .unwrap_or_else(|e| e);
-                        self.oam_sprites.insert(idx, sprite);
-                    }
+            if scanline < 144 {
+                // The first line after the LCD is switched on has no mode 2,
+                // and OAM stays accessible until mode 3.
+                if dot == 0 && !(self.first_lcd_frame && scanline == 0) {
+                    self.set_mode(PpuMode::OamScan);
+                    self.oam_blocked = true;
+                }
+                if dot == 0 {
+                    self.oam_sprites.clear();
+                    self.wy_triggered |= self.LY == self.WY;
+                }
+                // Two dots per OAM entry
+                if dot < 80
+                    && dot.is_multiple_of(2)
+                    && let Some(sprite) = self.fetch_sprite(dot as usize / 2)
+                    && self.oam_sprites.len() < 10
+                {
+                    self.oam_sprites.push(sprite);
+                }
+                if dot == 80 {
+                    self.set_mode(PpuMode::Drawing);
+                    self.oam_blocked = true;
+                    self.vram_blocked = true;
+                    self.start_line();
                 }
+                if self.mode == PpuMode::Drawing {
+                    self.draw_dot();
+                }
+            } else if scanline == 144 && dot == 0 {
+                self.set_mode(PpuMode::VBlank);
+                vblank = true;
+            } else if scanline == 153 && dot == 4 {
+                // On the second cycle of line 153, LY is set to 0, weirdly.
+                self.LY = 0;
             }
-        } else if scanline == 144 && clocks == 0 {
-            self.set_mode(PpuMode::VBlank);
-            vblank = true;
-        } else if scanline == 153 && clocks == 1 {
-            // On the second cycle of line 153, LY is set to 0, weirdly.
-            self.LY = 0;
-        }
 
-        let ly_coincidence = self.check_lyc();
-        let stat = self.check_stat(ly_coincidence);
+            let ly_coincidence = self.check_lyc();
+            stat |= self.check_stat(ly_coincidence);
+        }
 
         (vblank, stat)
     }
@@ -303,128 +617,289 @@ impl Ppu {
         }
     }
 
-    pub fn render(&mut self, pixels: &mut Pixels) -> Result<()> {
-        for (idx, pixel) in pixels.frame_mut().chunks_exact_mut(4).enumerate() {
-            let color = if self.LCDC.bit(7) && !self.first_lcd_frame {
-                self.viewport[idx / 160][idx % 160].color()
+    // Set on entering HBlank on a visible line, for HBlank DMA
+    pub fn hblank_check(&mut self) -> bool {
+        std::mem::take(&mut self.hblank)
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.framebuffer.as_slice()
+    }
+
+    pub fn shades(&self) -> &[u8] {
+        self.shades.as_slice()
+    }
+
+    fn update_framebuffer(&mut self) {
+        let pixels = self
+            .framebuffer
+            .chunks_exact_mut(4)
+            .zip(self.shades.iter_mut());
+        for (idx, (pixel, shade)) in pixels.enumerate() {
+            let p = if self.LCDC.bit(7) && !self.first_lcd_frame {
+                self.viewport[idx / 160][idx % 160]
             } else {
-                WHITE
+                Pixel {
+                    shade: 0,
+                    color: WHITE,
+                }
             };
-            pixel.copy_from_slice(&color);
+            *shade = p.shade;
+            pixel.copy_from_slice(&p.color);
         }
         self.first_lcd_frame = false;
-        pixels.render()?;
-        Ok(())
     }
 
-    fn draw_line(&mut self) {
-        if self.LCDC.bit(0) {
-            self.draw_bg_line();
-            if self.LCDC.bit(5) && self.LY >= self.WY {
-                self.draw_win_line();
+    fn start_line(&mut self) {
+        self.fetcher = Fetcher::default();
+        self.bg_fifo.clear();
+        self.sprite_fifo.clear();
+        self.sprite_fetch = None;
+        self.lx = 0;
+        // Fine scroll is applied by dropping pixels from the first tile
+        self.discard = self.SCX % 8;
+        // The first tile fetch of each line is thrown away
+        self.delay = 6;
+    }
+
+    // One dot of mode 3. The fetchers fill the FIFOs, and one pixel is shifted
+    // out to the LCD per dot unless a sprite fetch is stalling the pipeline.
+    fn draw_dot(&mut self) {
+        if self.lx == 160 {
+            if self.fetcher.window {
+                self.WC += 1;
             }
+            self.set_mode(PpuMode::HBlank);
+            self.oam_blocked = false;
+            self.vram_blocked = false;
+            self.hblank = true;
+            return;
         }
-        if self.LCDC.bit(1) {
-            self.draw_sprite_line();
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
         }
-    }
 
-    fn draw_bg_line(&mut self) {
-        let tilemap = self.LCDC.bit(3);
-        let y = self.SCY.wrapping_add(self.LY);
-        for tile in 0..32 {
-            let tile_row = self.get_tile_row(tilemap, y, tile);
-            for (i, &color_idx) in tile_row.iter().enumerate() {
-                let x = (8 * tile + i as u8).wrapping_sub(self.SCX) as usize;
-                if x < 160 {
-                    self.viewport[self.LY as usize][x] = Pixel {
-                        color_idx,
-                        palette: self.BGP,
-                    };
+        if self.sprite_fetch.is_none() && self.LCDC.bit(1) {
+            let next = self
+                .oam_sprites
+                .iter()
+                .enumerate()
+                .filter(|(_, sprite)| sprite.x <= self.lx + 8)
+                .min_by_key(|(_, sprite)| sprite.x)
+                .map(|(i, _)| i);
+            if let Some(i) = next {
+                self.sprite_fetch = Some(self.oam_sprites.remove(i));
+                self.sprite_dots = 0;
+            }
+        }
+        if self.sprite_fetch.is_some() {
+            // The background fetcher has to finish its current tile before
+            // the sprite fetch can start, which then takes 6 dots.
+            if self.fetcher.step < 6 {
+                self.tick_fetcher();
+            } else {
+                self.sprite_dots += 1;
+                if self.sprite_dots == 6 {
+                    let sprite = self.sprite_fetch.take().unwrap();
+                    self.merge_sprite(&sprite);
                 }
             }
+            return;
+        }
+
+        let window_enabled = self.LCDC.bit(5) && (self.LCDC.bit(0) || self.model.is_cgb());
+        if !self.fetcher.window && window_enabled && self.wy_triggered && self.lx + 7 >= self.WX {
+            self.bg_fifo.clear();
+            self.fetcher = Fetcher {
+                window: true,
+                ..Default::default()
+            };
+            self.discard = 7u8.saturating_sub(self.WX);
+        }
+
+        self.tick_fetcher();
+        let Some(bg) = self.bg_fifo.pop_front() else {
+            return;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
         }
+        let sprite = self.sprite_fifo.pop_front();
+        self.viewport[self.LY as usize][self.lx as usize] = self.mix_pixel(bg, sprite);
+        self.lx += 1;
     }
 
-    fn draw_win_line(&mut self) {
-        let tilemap = self.LCDC.bit(6);
-        let mut window_visible = false;
-        for tile in 0..32 {
-            let tile_row = self.get_tile_row(tilemap, self.WC, tile);
-            for (i, &color_idx) in tile_row.iter().enumerate() {
-                let x = 8 * tile as usize + i + self.WX as usize - 7;
-                if x < 160 {
-                    window_visible = true;
-                    self.viewport[self.LY as usize][x] = Pixel {
-                        color_idx,
-                        palette: self.BGP,
-                    };
-                }
+    fn tick_fetcher(&mut self) {
+        let cgb = self.model.is_cgb();
+        let (tilemap_bit, x, y) = if self.fetcher.window {
+            (self.LCDC.bit(6), self.fetcher.x, self.WC)
+        } else {
+            (
+                self.LCDC.bit(3),
+                ((self.SCX / 8) + self.fetcher.x) & 31,
+                self.SCY.wrapping_add(self.LY),
+            )
+        };
+        let attrs = self.fetcher.attrs;
+        let row = if attrs.bit(6) { 7 - y % 8 } else { y % 8 };
+        let bank = attrs.bit(3) as u8;
+
+        match self.fetcher.step {
+            1 => {
+                let tilemap = if tilemap_bit { 0x9c00 } else { 0x9800 };
+                let map_addr = tilemap + 32 * (y as u16 / 8) + x as u16;
+                self.fetcher.tile = self.vram_bank(0, map_addr);
+                self.fetcher.attrs = if cgb { self.vram_bank(1, map_addr) } else { 0 };
+            }
+            3 => {
+                let addr = self.tile_addr(self.fetcher.tile, row, false);
+                self.fetcher.lo = self.vram_bank(bank, addr);
             }
+            5 => {
+                let addr = self.tile_addr(self.fetcher.tile, row, false);
+                self.fetcher.hi = self.vram_bank(bank, addr + 1);
+            }
+            _ => {}
         }
-        if window_visible {
-            self.WC += 1;
+
+        if self.fetcher.step < 6 {
+            self.fetcher.step += 1;
+        } else if self.bg_fifo.is_empty() {
+            let mut tile_row = decode_tile_row(self.fetcher.lo, self.fetcher.hi);
+            if attrs.bit(5) {
+                tile_row.reverse();
+            }
+            self.bg_fifo.extend(tile_row.map(|color_idx| FifoPixel {
+                color_idx,
+                palette: attrs & 0b111,
+                priority: attrs.bit(7),
+                oam_index: 0,
+            }));
+            self.fetcher.step = 0;
+            self.fetcher.x = self.fetcher.x.wrapping_add(1);
         }
     }
 
-    fn draw_sprite_line(&mut self) {
+    fn merge_sprite(&mut self, sprite: &Sprite) {
+        let cgb = self.model.is_cgb();
         let height = if self.LCDC.bit(2) { 16 } else { 8 };
-        for sprite in &self.oam_sprites {
-            let mut row = self.LY + 16 - sprite.y;
-            if sprite.y_flip {
-                row = height - row - 1;
+        let mut row = self.LY + 16 - sprite.y;
+        if sprite.y_flip {
+            row = height - row - 1;
+        }
+        let (palette, bank) = if cgb {
+            (sprite.cgb_palette, sprite.bank as u8)
+        } else {
+            (sprite.palette as u8, 0)
+        };
+        let tile = sprite.tile & (0xFF - height / 8 + 1);
+        let addr = self.tile_addr(tile, row, true);
+        let mut tile_row =
+            decode_tile_row(self.vram_bank(bank, addr), self.vram_bank(bank, addr + 1));
+        if sprite.x_flip {
+            tile_row.reverse();
+        }
+
+        // Sprites partially off the left edge of the screen lose their first
+        // few pixels
+        let skip = ((self.lx + 8 - sprite.x) as usize).min(8);
+        while self.sprite_fifo.len() < 8 - skip {
+            self.sprite_fifo.push_back(FifoPixel::default());
+        }
+        // Sprites are fetched by X coordinate, so on DMG the pixels already in
+        // the FIFO have priority. CGB gives priority to the lowest OAM index
+        // instead, unless OPRI is set.
+        let oam_priority = cgb && !self.OPRI.bit(0);
+        for (slot, &color_idx) in self.sprite_fifo.iter_mut().zip(&tile_row[skip..]) {
+            if color_idx != 0
+                && (slot.color_idx == 0 || (oam_priority && sprite.index < slot.oam_index))
+            {
+                *slot = FifoPixel {
+                    color_idx,
+                    palette,
+                    priority: sprite.priority,
+                    oam_index: sprite.index,
+                };
             }
-            let palette = if sprite.palette { self.OBP1 } else { self.OBP0 };
-            let tile = sprite.tile & (0xFF - height / 8 + 1);
-            let tile_row = self.decode_tile_row(tile, row, true);
-
-            let scanline = &mut self.viewport[self.LY as usize];
-            for i in 0..8 {
-                let col = if sprite.x_flip { 7 - i } else { i };
-                let color_idx = tile_row[col];
-                let x = (sprite.x + i as u8).wrapping_sub(8);
-                if x < 160
-                    && color_idx != 0
-                    && (!sprite.priority || scanline[x as usize].color_idx == 0)
-                {
-                    scanline[x as usize] = Pixel { color_idx, palette };
-                }
+        }
+    }
+
+    fn mix_pixel(&self, bg: FifoPixel, sprite: Option<FifoPixel>) -> Pixel {
+        let cgb = self.model.is_cgb();
+        // On CGB, LCDC bit 0 is the BG master priority instead of a BG enable
+        let bg_enabled = cgb || self.LCDC.bit(0);
+        let bg = if bg_enabled { bg } else { FifoPixel::default() };
+        let sprite = sprite.filter(|sprite| {
+            if !self.LCDC.bit(1) || sprite.color_idx == 0 {
+                false
+            } else if cgb {
+                !self.LCDC.bit(0) || bg.color_idx == 0 || !(bg.priority || sprite.priority)
+            } else {
+                !sprite.priority || bg.color_idx == 0
             }
+        });
+        if cgb {
+            let (p, palettes) = match sprite {
+                Some(sprite) => (sprite, &self.obj_palettes),
+                None => (bg, &self.bg_palettes),
+            };
+            return Pixel {
+                shade: p.color_idx,
+                color: cgb_color(palettes, p.palette, p.color_idx),
+            };
+        }
+
+        let shade = match sprite {
+            Some(sprite) => {
+                let palette = if sprite.palette == 1 {
+                    self.OBP1
+                } else {
+                    self.OBP0
+                };
+                (palette >> (2 * sprite.color_idx)) & 0b11
+            }
+            // A disabled background is blank, regardless of BGP
+            None if !bg_enabled => 0,
+            None => (self.BGP >> (2 * bg.color_idx)) & 0b11,
+        };
+        Pixel {
+            shade,
+            color: shade_color(shade),
         }
     }
 
     fn fetch_sprite(&self, idx: usize) -> Option<Sprite> {
         let sprite_height = if self.LCDC.bit(2) { 16 } else { 8 };
-        let sprite = Sprite::from_oam_data(self.oam_ram[4 * idx..4 * idx + 4].try_into().unwrap());
-        let y = self.LY + 16;
-        if sprite.x > 0 && (sprite.y..sprite.y + sprite_height).contains(&y) {
+        let sprite = Sprite::from_oam_data(
+            idx as u8,
+            self.oam_ram[4 * idx..4 * idx + 4].try_into().unwrap(),
+        );
+        // Y can be up to FF, so the bottom edge needs more than a u8
+        let y = self.LY as u16 + 16;
+        let top = sprite.y as u16;
+        if sprite.x > 0 && (top..top + sprite_height).contains(&y) {
             Some(sprite)
         } else {
             None
         }
     }
 
-    fn get_tile_row(&self, tilemap_bit: bool, line: u8, tile: u8) -> [u8; 8] {
-        let tilemap = if tilemap_bit { 0x9c00 } else { 0x9800 };
-        let tile_num = self.read_vram(tilemap + 32 * (line as u16 / 8) + tile as u16);
-        self.decode_tile_row(tile_num, line % 8, false)
-    }
-
-    fn decode_tile_row(&self, tile_num: u8, row_num: u8, is_sprite: bool) -> [u8; 8] {
+    fn tile_addr(&self, tile_num: u8, row_num: u8, is_sprite: bool) -> u16 {
         let tile_addr = if self.LCDC.bit(4) || is_sprite {
             0x8000 + 16 * tile_num as u16
         } else {
             0x9000u16.wrapping_add_signed(16 * tile_num as i8 as i16)
         };
+        tile_addr + 2 * row_num as u16
+    }
+}
 
-        let row_addr = tile_addr + 2 * row_num as u16;
-        let hi = self.read_vram(row_addr + 1);
-        let lo = self.read_vram(row_addr);
-
-        let mut row = [0; 8];
-        for col in 0..8 {
-            row[7 - col] = (((hi >> col) & 1) << 1) | ((lo >> col) & 1);
-        }
-        row
+fn decode_tile_row(lo: u8, hi: u8) -> [u8; 8] {
+    let mut row = [0; 8];
+    for col in 0..8 {
+        row[7 - col] = (((hi >> col) & 1) << 1) | ((lo >> col) & 1);
     }
+    row
 }
@@ -0,0 +1,173 @@
+use anyhow::{Result, bail};
+use std::collections::VecDeque;
+
+pub const MAGIC: &[u8; 8] = b"RGBSTATE";
+
+// Bump whenever the layout of any saved component changes, so that stale
+// states are rejected instead of being misinterpreted.
+pub const VERSION: u16 = 9;
+
+// State is written as a flat byte buffer with no framing, and restored in
+// place. Both sides must visit the same fields in the same order.
+pub trait Savestate {
+    fn save_state(&self, state: &mut Vec<u8>);
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()>;
+}
+
+macro_rules! impl_savestate {
+    ($t:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::savestate::Savestate for $t {
+            fn save_state(&self, state: &mut Vec<u8>) {
+                $($crate::savestate::Savestate::save_state(&self.$field, state);)*
+            }
+
+            fn load_state(&mut self, state: &mut &[u8]) -> anyhow::Result<()> {
+                $($crate::savestate::Savestate::load_state(&mut self.$field, state)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use impl_savestate;
+
+pub fn take<const N: usize>(state: &mut &[u8]) -> Result<[u8; N]> {
+    let Some((bytes, rest)) = state.split_first_chunk::<N>() else {
+        bail!("Savestate is truncated");
+    };
+    *state = rest;
+    Ok(*bytes)
+}
+
+macro_rules! impl_savestate_int {
+    ($($t:ty),*) => {
+        $(
+            impl Savestate for $t {
+                fn save_state(&self, state: &mut Vec<u8>) {
+                    state.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+                    *self = <$t>::from_le_bytes(take(state)?);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_savestate_int!(u8, u16, u32, u64);
+
+impl Savestate for bool {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        let [byte] = take(state)?;
+        *self = match byte {
+            0 => false,
+            1 => true,
+            _ => bail!("Invalid bool in savestate: {byte:02x}"),
+        };
+        Ok(())
+    }
+}
+
+impl<T: Savestate, const N: usize> Savestate for [T; N] {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        for item in self {
+            item.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        for item in self {
+            item.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Savestate + Default> Savestate for Vec<T> {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        (self.len() as u32).save_state(state);
+        for item in self {
+            item.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        let len = u32::from_le_bytes(take(state)?) as usize;
+        self.clear();
+        for _ in 0..len {
+            let mut item = T::default();
+            item.load_state(state)?;
+            self.push(item);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Savestate + Default> Savestate for VecDeque<T> {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        (self.len() as u32).save_state(state);
+        for item in self {
+            item.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        let len = u32::from_le_bytes(take(state)?) as usize;
+        self.clear();
+        for _ in 0..len {
+            let mut item = T::default();
+            item.load_state(state)?;
+            self.push_back(item);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Savestate + ?Sized> Savestate for Box<T> {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        (**self).save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        (**self).load_state(state)
+    }
+}
+
+impl<T: Savestate + Default> Savestate for Option<T> {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.is_some().save_state(state);
+        if let Some(val) = self {
+            val.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        let mut is_some = false;
+        is_some.load_state(state)?;
+        *self = if is_some {
+            let mut val = T::default();
+            val.load_state(state)?;
+            Some(val)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl<A: Savestate, B: Savestate> Savestate for (A, B) {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.0.save_state(state);
+        self.1.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut &[u8]) -> Result<()> {
+        self.0.load_state(state)?;
+        self.1.load_state(state)
+    }
+}
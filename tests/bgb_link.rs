@@ -0,0 +1,147 @@
+// The frontend's BGB link cable over loopback TCP, against a peer scripted
+// packet by packet, and between two Game Boys on their own threads
+
+#[allow(dead_code)]
+#[path = "../src/frontend/link.rs"]
+mod link;
+
+mod common;
+
+use link::BgbLink;
+use rgb::{Gameboy, SerialDevice};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const VERSION: u8 = 1;
+const SYNC1: u8 = 104;
+const SYNC2: u8 = 105;
+const SYNC3: u8 = 106;
+const STATUS: u8 = 108;
+
+// Connects a scripted peer that announces `version`, and accepts it
+fn connect(version: [u8; 8]) -> (anyhow::Result<BgbLink>, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    peer.write_all(&version).unwrap();
+    (BgbLink::accept(&listener), peer)
+}
+
+fn read(peer: &mut TcpStream) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    peer.read_exact(&mut bytes).unwrap();
+    bytes
+}
+
+fn timestamp(bytes: [u8; 8]) -> u32 {
+    u32::from_le_bytes(bytes[4..].try_into().unwrap())
+}
+
+// Accepts a peer and reads through its greeting
+fn connected() -> (BgbLink, TcpStream) {
+    let (link, mut peer) = connect([VERSION, 1, 4, 0, 0, 0, 0, 0]);
+    let link = link.unwrap();
+    assert_eq!(read(&mut peer)[..3], [VERSION, 1, 4]);
+    assert_eq!(read(&mut peer)[..2], [STATUS, 1]);
+    assert_eq!(read(&mut peer)[0], SYNC3);
+    (link, peer)
+}
+
+#[test]
+fn version_patch_and_timestamp_are_ignored() {
+    let (link, _peer) = connect([VERSION, 1, 4, 9, 0x34, 0x12, 0, 0]);
+    assert!(link.is_ok());
+}
+
+#[test]
+fn other_versions_are_refused() {
+    let (link, _peer) = connect([VERSION, 2, 0, 0, 0, 0, 0, 0]);
+    assert!(link.is_err());
+}
+
+#[test]
+fn transfer_waits_for_the_reply_without_blocking() {
+    let (mut link, mut peer) = connected();
+    assert_eq!(link.transfer(0x42), None);
+    assert_eq!(read(&mut peer)[..3], [SYNC1, 0x42, 0x81]);
+    assert_eq!(link.poll_reply(), None);
+
+    peer.write_all(&[SYNC2, 0x99, 0x80, 0, 0, 0, 0, 0]).unwrap();
+    let mut reply = None;
+    for _ in 0..1000 {
+        link.tick(false, false);
+        reply = link.poll_reply();
+        if reply.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(reply, Some(0x99));
+}
+
+#[test]
+fn peer_that_is_not_waiting_acks_with_ff() {
+    let (mut link, mut peer) = connected();
+    assert_eq!(link.transfer(0x42), None);
+    assert_eq!(read(&mut peer)[0], SYNC1);
+    peer.write_all(&[SYNC3, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    link.tick(false, false);
+    assert_eq!(link.poll_reply(), Some(0xff));
+}
+
+// Timestamps count 2 MiHz ticks, so an mcycle is twice as long at normal
+// speed
+#[test]
+fn timestamps_follow_double_speed() {
+    for (double_speed, mcycles) in [(false, 2048), (true, 4096)] {
+        let (mut link, mut peer) = connected();
+        for _ in 0..mcycles {
+            link.tick(false, double_speed);
+        }
+        let sync = read(&mut peer);
+        assert_eq!(sync[0], SYNC3);
+        assert_eq!(timestamp(sync), 4096);
+    }
+}
+
+// The master clocks a counter, the slave replies with the last byte it
+// received plus 0x10
+#[test]
+fn gameboys_exchange_bytes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let slave = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            let mut gb = Gameboy::new(common::rom(common::SLAVE), None).unwrap();
+            gb.connect_serial(Box::new(BgbLink::accept(&listener).unwrap()));
+            while !stop.load(Ordering::Relaxed) {
+                gb.run_frame().unwrap();
+            }
+            [0xc000, 0xc001].map(|addr| gb.read_memory(addr))
+        }
+    });
+    let mut gb = Gameboy::new(common::rom(common::MASTER), None).unwrap();
+    gb.connect_serial(Box::new(BgbLink::connect(&addr).unwrap()));
+    common::run_frames(&mut gb, 5);
+    let (reply, sent) = (gb.read_memory(0xc000), gb.read_memory(0xc001));
+    // Hang up before the slave stops, so it isn't left waiting for us
+    drop(gb);
+    stop.store(true, Ordering::Relaxed);
+    let [last, received] = slave.join().unwrap();
+
+    assert!(sent > 1, "only {sent} transfers");
+    assert_eq!(reply, sent.wrapping_add(0x0f));
+    assert_eq!(last, received);
+    assert!(
+        received.wrapping_sub(sent) <= 1,
+        "sent {sent}, received {received}"
+    );
+}
@@ -0,0 +1,19 @@
+// Which addresses the bootrom covers while it's mapped
+
+use rgb::Gameboy;
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0000] = 0x11;
+    rom[0x0200] = 0x22;
+    rom
+}
+
+#[test]
+fn dmg_bootrom_ends_at_0100() {
+    let gb = Gameboy::new(rom(), Some(vec![0xaa; 0x100])).unwrap();
+    assert_eq!(gb.read_memory(0x0000), 0xaa);
+    // The CGB bootrom continues here, the DMG one doesn't
+    assert_eq!(gb.read_memory(0x0200), 0x22);
+    assert_eq!(gb.read_memory(0x08ff), 0x00);
+}
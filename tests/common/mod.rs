@@ -0,0 +1,49 @@
+// Helpers shared by the tests that run tiny hand-assembled ROMs. Not every
+// test file uses all of them.
+#![allow(dead_code)]
+
+use rgb::Gameboy;
+
+// A 32 KiB ROM that jumps from the entry point to `program` at 0150
+pub fn rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // NOP; JP 0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x150..0x150 + program.len()].copy_from_slice(program);
+    rom
+}
+
+pub fn run_frames(gb: &mut Gameboy, frames: u32) {
+    for _ in 0..frames {
+        gb.run_frame().unwrap();
+    }
+}
+
+// A pair of link cable programs. The master clocks a counter out, storing
+// each reply at C000 and the counter at C001. The slave replies with the last
+// byte it received plus 0x10, storing it at C000 and counting them at C001.
+#[rustfmt::skip]
+pub const MASTER: &[u8] = &[
+    0x06, 0x00,             // LD B,00
+    0x04,                   // loop: INC B
+    0x78, 0xe0, 0x01,       // LD A,B; LDH (01),A
+    0x3e, 0x81, 0xe0, 0x02, // LD A,81; LDH (02),A
+    0xf0, 0x02, 0xcb, 0x7f, // wait: LDH A,(02); BIT 7,A
+    0x20, 0xfa,             // JR NZ,wait
+    0xf0, 0x01, 0xea, 0x00, 0xc0, // LDH A,(01); LD (C000),A
+    0x78, 0xea, 0x01, 0xc0, // LD A,B; LD (C001),A
+    0x18, 0xe7,             // JR loop
+];
+
+#[rustfmt::skip]
+pub const SLAVE: &[u8] = &[
+    0x06, 0x10,             // LD B,10
+    0x78, 0xe0, 0x01,       // loop: LD A,B; LDH (01),A
+    0x3e, 0x80, 0xe0, 0x02, // LD A,80; LDH (02),A
+    0xf0, 0x02, 0xcb, 0x7f, // wait: LDH A,(02); BIT 7,A
+    0x20, 0xfa,             // JR NZ,wait
+    0xf0, 0x01, 0xea, 0x00, 0xc0, // LDH A,(01); LD (C000),A
+    0xc6, 0x10, 0x47,       // ADD A,10; LD B,A
+    0x21, 0x01, 0xc0, 0x34, // LD HL,C001; INC (HL)
+    0x18, 0xe5,             // JR loop
+];
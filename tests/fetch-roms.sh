@@ -0,0 +1,38 @@
+#!/bin/sh
+# Downloads the blargg, mooneye and dmg-acid2 test ROMs into tests/roms, from
+# a pinned release of the game-boy-test-roms collection, and checks that
+# every ROM tests/test_roms.rs uses is there.
+set -eu
+
+VERSION=v7.0
+URL=https://github.com/c-sp/game-boy-test-roms/releases/download/$VERSION/game-boy-test-roms-$VERSION.zip
+
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+curl -fL -o "$tmp/roms.zip" "$URL"
+unzip -q "$tmp/roms.zip" -d "$tmp/roms"
+
+rm -rf roms
+mkdir roms
+cp -r "$tmp/roms/blargg" roms/blargg
+cp -r "$tmp/roms/mooneye-test-suite" roms/mooneye
+cp -r "$tmp/roms/dmg-acid2" roms/dmg-acid2
+if [ ! -e roms/dmg-acid2/reference-dmg.png ]; then
+    cp roms/dmg-acid2/dmg-acid2-dmg.png roms/dmg-acid2/reference-dmg.png
+fi
+
+# Some names have spaces in them
+missing=$(
+    { sed -n 's/.*=> "\(.*\)",$/\1/p' test_roms.rs; echo dmg-acid2/reference-dmg.png; } |
+        while IFS= read -r rom; do
+            [ -e "roms/$rom" ] || echo "missing: $rom"
+        done
+)
+if [ -n "$missing" ]; then
+    echo "$missing"
+    echo "The $VERSION layout doesn't match tests/test_roms.rs" >&2
+    exit 1
+fi
+echo "Test ROMs $VERSION ready in tests/roms"
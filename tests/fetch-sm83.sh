@@ -0,0 +1,29 @@
+#!/bin/sh
+# Downloads the SM83 single step tests (https://github.com/SingleStepTests/sm83)
+# into tests/sm83 for tests/sm83.rs. REV picks the commit to fetch. The commit
+# actually fetched is written to tests/sm83/COMMIT, so a run can be repeated
+# against the same vectors with REV=$(cat tests/sm83/COMMIT).
+set -eu
+
+REV=${REV:-main}
+URL=https://github.com/SingleStepTests/sm83/archive/$REV.tar.gz
+
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+curl -fL -o "$tmp/sm83.tar.gz" "$URL"
+tar -xzf "$tmp/sm83.tar.gz" -C "$tmp"
+commit=$(gzip -dc "$tmp/sm83.tar.gz" | git get-tar-commit-id)
+
+rm -rf sm83
+mkdir sm83
+cp "$tmp"/sm83-*/v1/*.json sm83/
+echo "$commit" >sm83/COMMIT
+
+count=$(find sm83 -name '*.json' | wc -l)
+if [ "$count" -eq 0 ]; then
+    echo "No test vectors in v1/ at $commit" >&2
+    exit 1
+fi
+echo "$count SM83 test files from $commit ready in tests/sm83"
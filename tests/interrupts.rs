@@ -0,0 +1,101 @@
+// Interrupt edge cases, with tiny ROMs that store what happened in WRAM and
+// then spin. The VBlank handler, if there is one, goes at 0040.
+
+mod common;
+
+use rgb::Gameboy;
+
+fn rom(program: &[u8], vblank_handler: &[u8]) -> Vec<u8> {
+    let mut rom = common::rom(program);
+    rom[0x40..0x40 + vblank_handler.len()].copy_from_slice(vblank_handler);
+    rom
+}
+
+fn run(rom: Vec<u8>) -> Gameboy {
+    let mut gb = Gameboy::new(rom, None).unwrap();
+    common::run_frames(&mut gb, 5);
+    gb
+}
+
+#[rustfmt::skip]
+const STORE_C: &[u8] = &[
+    0x79, 0xea, 0x00, 0xc0, // LD A,C; LD (C000),A
+    0x18, 0xfe,             // JR $
+];
+
+#[test]
+fn ei_then_di_takes_no_interrupt() {
+    #[rustfmt::skip]
+    let program = [
+        0x3e, 0x01, 0xe0, 0xff, // LD A,01; LDH (FF),A
+        0xe0, 0x0f,             // LDH (0F),A
+        0xfb, 0xf3,             // EI; DI
+        0x3e, 0x42, 0xea, 0x00, 0xc0, // LD A,42; LD (C000),A
+        0x18, 0xfe,             // JR $
+    ];
+    #[rustfmt::skip]
+    let handler = [
+        0x3e, 0x99, 0xea, 0x01, 0xc0, // LD A,99; LD (C001),A
+        0x18, 0xfe,                   // JR $
+    ];
+    let gb = run(rom(&program, &handler));
+    assert_eq!(gb.read_memory(0xc000), 0x42);
+    assert_ne!(gb.read_memory(0xc001), 0x99);
+}
+
+#[test]
+fn ei_takes_effect_after_the_next_instruction() {
+    #[rustfmt::skip]
+    let program = [
+        0x0e, 0x00,             // LD C,00
+        0x3e, 0x01, 0xe0, 0xff, // LD A,01; LDH (FF),A
+        0xe0, 0x0f,             // LDH (0F),A
+        0xfb,                   // EI
+        0x0c, 0x0c, 0x0c,       // INC C; INC C; INC C
+        0x18, 0xfe,             // JR $
+    ];
+    let gb = run(rom(&program, STORE_C));
+    assert_eq!(gb.read_memory(0xc000), 1);
+}
+
+// HALT with IME=0 and an interrupt already pending doesn't halt, and the byte
+// after it is read twice
+#[test]
+fn halt_bug() {
+    for (pending, expected) in [(0x01, 2), (0x00, 1)] {
+        #[rustfmt::skip]
+        let program = [
+            0x0e, 0x00,             // LD C,00
+            0x3e, 0x01, 0xe0, 0xff, // LD A,01; LDH (FF),A
+            0x3e, pending, 0xe0, 0x0f, // LD A,pending; LDH (0F),A
+            0x76,                   // HALT
+            0x0c,                   // INC C
+            0x79, 0xea, 0x00, 0xc0, // LD A,C; LD (C000),A
+            0x18, 0xfe,             // JR $
+        ];
+        let gb = run(rom(&program, &[]));
+        assert_eq!(gb.read_memory(0xc000), expected, "IF={pending:02x}");
+    }
+}
+
+// On DMG, writing STAT outside of modes 2 and 3 briefly enables every STAT
+// interrupt source, which requests an interrupt
+#[test]
+fn dmg_stat_write_requests_an_interrupt() {
+    #[rustfmt::skip]
+    let program = [
+        0xf0, 0x44, 0xfe, 0x90, // wait: LDH A,(44); CP 90
+        0x20, 0xfa,             // JR NZ,wait
+        0xaf, 0xe0, 0x0f,       // XOR A; LDH (0F),A
+        0xe0, 0x41,             // LDH (41),A
+        0xf0, 0x0f, 0xea, 0x00, 0xc0, // LDH A,(0F); LD (C000),A
+        0x18, 0xfe,             // JR $
+    ];
+    let gb = run(rom(&program, &[]));
+    assert_eq!(gb.read_memory(0xc000) & 0x02, 0x02);
+
+    let mut cgb_rom = rom(&program, &[]);
+    cgb_rom[0x143] = 0x80;
+    let gb = run(cgb_rom);
+    assert_eq!(gb.read_memory(0xc000) & 0x02, 0x00);
+}
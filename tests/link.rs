@@ -0,0 +1,117 @@
+// Two Game Boys linked in one process, running tiny ROMs that trade bytes over
+// the link cable. One side clocks the transfers with a counter, the other
+// replies with the last byte it received plus 0x10.
+
+mod common;
+
+use common::{MASTER, SLAVE, rom};
+use rgb::{FourPlayerAdapter, Gameboy, LinkedGameboys};
+
+fn run(frames: u32) -> LinkedGameboys {
+    let master = Gameboy::new(rom(MASTER), None).unwrap();
+    let slave = Gameboy::new(rom(SLAVE), None).unwrap();
+    let mut linked = LinkedGameboys::new([master, slave]);
+    for _ in 0..frames {
+        linked.run_frame().unwrap();
+    }
+    linked
+}
+
+#[test]
+fn bytes_are_exchanged() {
+    let linked = run(10);
+    let [master, slave] = linked.gameboys();
+    let (sent, received) = (master.read_memory(0xc001), slave.read_memory(0xc001));
+    assert!(sent > 1, "only {sent} transfers");
+    // Every byte reached the slave, which may already have the one in flight
+    // and may not have counted the last one yet
+    let last = slave.read_memory(0xc000);
+    assert!(
+        last.wrapping_sub(received) <= 1,
+        "{received} counted, last {last}"
+    );
+    assert!(last.wrapping_sub(sent) <= 1, "sent {sent}, received {last}");
+    // Every reply was the previous byte plus 0x10
+    assert_eq!(master.read_memory(0xc000), sent.wrapping_add(0x0f));
+}
+
+// They're run an M-cycle at a time, and stop between instructions at the
+// same point in time
+#[test]
+fn frames_end_on_the_same_mcycle() {
+    let master = Gameboy::new(rom(MASTER), None).unwrap();
+    let slave = Gameboy::new(rom(SLAVE), None).unwrap();
+    let mut linked = LinkedGameboys::new([master, slave]);
+    for _ in 0..5 {
+        linked.run_frame().unwrap();
+        let [master, slave] = linked.gameboys();
+        assert_eq!(master.cycles(), slave.cycles());
+    }
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let a = run(5);
+    let b = run(5);
+    for (a, b) in a.gameboys().iter().zip(b.gameboys()) {
+        assert_eq!(a.cycles(), b.cycles());
+        assert_eq!(a.savestate(), b.savestate());
+    }
+}
+
+// Replies to pings with ACK ACK RATE SIZE (rate 0, one byte per player), then
+// sends `data` from the fifth transfer on. Every byte received is stored at
+// C100 onwards.
+#[rustfmt::skip]
+fn adapter_rom(data: u8) -> Vec<u8> {
+    let mut rom = rom(&[
+        0x06, 0x00,             // LD B,00
+        0x78, 0xfe, 0x10,       // loop: LD A,B; CP 10
+        0x3e, data,             // LD A,data
+        0x30, 0x07,             // JR NC,send
+        0x78, 0xe6, 0x03, 0x6f, // LD A,B; AND 03; LD L,A
+        0x26, 0x40, 0x7e,       // LD H,40; LD A,(HL)
+        0xe0, 0x01,             // send: LDH (01),A
+        0x3e, 0x80, 0xe0, 0x02, // LD A,80; LDH (02),A
+        0xf0, 0x02, 0xcb, 0x7f, // wait: LDH A,(02); BIT 7,A
+        0x20, 0xfa,             // JR NZ,wait
+        0xf0, 0x01,             // LDH A,(01)
+        0x26, 0xc1, 0x68, 0x77, // LD H,C1; LD L,B; LD (HL),A
+        0x04,                   // INC B
+        0x18, 0xdd,             // JR loop
+    ]);
+    rom[0x4000..0x4004].copy_from_slice(&[0x88, 0x88, 0x00, 0x01]);
+    rom
+}
+
+#[test]
+fn four_player_adapter() {
+    // Player 1 starts the transmission phase
+    let gameboys = [0xaa, 0x22, 0x33, 0x44]
+        .map(|data| Gameboy::new(adapter_rom(data), None).unwrap())
+        .into();
+    let mut adapter = FourPlayerAdapter::new(gameboys).unwrap();
+    for _ in 0..8 {
+        adapter.run_frame().unwrap();
+    }
+    for (player, gb) in adapter.gameboys().iter().enumerate() {
+        let received: Vec<u8> = (0xc100..0xc120).map(|addr| gb.read_memory(addr)).collect();
+        let id = player as u8 + 1;
+        // Nobody has acknowledged the first ping yet, then everyone has
+        assert_eq!(received[0..4], [0xfe, id, id, id]);
+        assert_eq!(received[4..8], [0xfe, 0xf0 | id, 0xf0 | id, 0xf0 | id]);
+        // Player 1's 0xaa finishes the ping, then is confirmed
+        assert_eq!(received[20..24], [0xcc; 4]);
+        // Nothing was collected before the first cycle
+        assert_eq!(received[24..28], [0x00; 4]);
+        assert_eq!(received[28..32], [0xaa, 0x22, 0x33, 0x44]);
+    }
+}
+
+#[test]
+fn four_player_adapter_has_four_ports() {
+    let gameboys = || (0..5).map(|_| Gameboy::new(adapter_rom(0), None).unwrap());
+    assert!(FourPlayerAdapter::new(gameboys().collect()).is_err());
+    assert!(FourPlayerAdapter::new(Vec::new()).is_err());
+    assert!(FourPlayerAdapter::new(gameboys().take(4).collect()).is_ok());
+}
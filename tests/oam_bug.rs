@@ -0,0 +1,57 @@
+// The DMG OAM corruption bug: incrementing or decrementing a register pair
+// that points into FE00-FEFF while the PPU is scanning OAM smears OAM rows.
+
+mod common;
+
+use rgb::Gameboy;
+
+// Fills OAM with 00-9F, turns the LCD on, runs INC HL; DEC HL 4096 times
+// with HL at `addr`, and turns the LCD off again
+fn run(addr: u16, cgb: bool) -> Vec<u8> {
+    let [lo, hi] = addr.to_le_bytes();
+    #[rustfmt::skip]
+    let program = [
+        0xaf, 0xe0, 0x40,       // XOR A; LDH (40),A
+        0x21, 0x00, 0xfe,       // LD HL,FE00
+        0x06, 0xa0,             // LD B,A0
+        0x22, 0x3c,             // fill: LD (HL+),A; INC A
+        0x05, 0x20, 0xfb,       // DEC B; JR NZ,fill
+        0x3e, 0x91, 0xe0, 0x40, // LD A,91; LDH (40),A
+        0x21, lo, hi,           // LD HL,addr
+        0x11, 0x00, 0x10,       // LD DE,1000
+        0x23, 0x2b,             // loop: INC HL; DEC HL
+        0x1d, 0x20, 0xfb,       // DEC E; JR NZ,loop
+        0x15, 0x20, 0xf8,       // DEC D; JR NZ,loop
+        0xaf, 0xe0, 0x40,       // XOR A; LDH (40),A
+        0x18, 0xfe,             // JR $
+    ];
+    let mut rom = common::rom(&program);
+    if cgb {
+        rom[0x143] = 0x80;
+    }
+    let mut gb = Gameboy::new(rom, None).unwrap();
+    common::run_frames(&mut gb, 5);
+    (0xfe00..0xfea0).map(|addr| gb.read_memory(addr)).collect()
+}
+
+fn filled() -> Vec<u8> {
+    (0..0xa0).collect()
+}
+
+#[test]
+fn dmg_inc_dec_in_oam_corrupts_it() {
+    let oam = run(0xfe40, false);
+    assert_ne!(oam, filled());
+    // Row 0 is never corrupted
+    assert_eq!(oam[..8], filled()[..8]);
+}
+
+#[test]
+fn inc_dec_outside_oam_leaves_it_alone() {
+    assert_eq!(run(0xc000, false), filled());
+}
+
+#[test]
+fn cgb_has_no_oam_bug() {
+    assert_eq!(run(0xfe40, true), filled());
+}
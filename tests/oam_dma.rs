@@ -0,0 +1,92 @@
+// OAM DMA as seen by a program running from HRAM, with the LCD off so only
+// the DMA gets in the way of OAM. C100-C19F holds 00-9F and C200-C29F holds
+// FF-60 as sources.
+
+mod common;
+
+use rgb::Gameboy;
+
+#[rustfmt::skip]
+const SETUP: &[u8] = &[
+    0xf0, 0x44, 0xfe, 0x90, // wait: LDH A,(44); CP 90
+    0x20, 0xfa,             // JR NZ,wait
+    0xaf, 0xe0, 0x40,       // XOR A; LDH (40),A
+    0x21, 0x00, 0xc1,       // LD HL,C100
+    0x7d, 0x2f, 0x24, 0x77, // fill: LD A,L; CPL; INC H; LD (HL),A
+    0x25, 0x2f, 0x22,       // DEC H; CPL; LD (HL+),A
+    0x7d, 0xfe, 0xa0,       // LD A,L; CP A0
+    0x20, 0xf4,             // JR NZ,fill
+    0x21, 0x00, 0x02,       // LD HL,0200
+    0x0e, 0x80,             // LD C,80
+    0x2a, 0xe2, 0x0c,       // copy: LD A,(HL+); LD (FF00+C),A; INC C
+    0x79, 0xfe, 0xc0,       // LD A,C; CP C0
+    0x20, 0xf8,             // JR NZ,copy
+    0xcd, 0x80, 0xff,       // CALL FF80
+    0x18, 0xfe,             // JR $
+];
+
+// Copies `routine` to FF80 and calls it
+fn run(routine: &[u8]) -> Gameboy {
+    let mut rom = common::rom(SETUP);
+    rom[0] = 0xee;
+    rom[0x200..0x200 + routine.len()].copy_from_slice(routine);
+    let mut gb = Gameboy::new(rom, None).unwrap();
+    common::run_frames(&mut gb, 5);
+    gb
+}
+
+fn oam(gb: &Gameboy) -> Vec<u8> {
+    (0xfe00..0xfea0).map(|addr| gb.read_memory(addr)).collect()
+}
+
+#[rustfmt::skip]
+const WAIT_AND_RETURN: &[u8] = &[
+    0x3e, 0x40, 0x3d,       // LD A,40; wait: DEC A
+    0x20, 0xfd,             // JR NZ,wait
+    0xc9,                   // RET
+];
+
+#[test]
+fn copies_160_bytes() {
+    let mut routine = vec![0x3e, 0xc1, 0xe0, 0x46]; // LD A,C1; LDH (46),A
+    routine.extend(WAIT_AND_RETURN);
+    let gb = run(&routine);
+    assert_eq!(oam(&gb), (0..0xa0).collect::<Vec<u8>>());
+}
+
+#[test]
+fn cpu_is_locked_out_of_oam_and_the_source_bus() {
+    #[rustfmt::skip]
+    let mut routine = vec![
+        0x3e, 0xc1, 0xe0, 0x46, // LD A,C1; LDH (46),A
+        0xfa, 0x00, 0xfe,       // LD A,(FE00)
+        0xe0, 0xf0,             // LDH (F0),A
+        // ROM is on the same bus as WRAM
+        0xfa, 0x00, 0x00,       // LD A,(0000)
+        0xe0, 0xf1,             // LDH (F1),A
+    ];
+    routine.extend(WAIT_AND_RETURN);
+    let gb = run(&routine);
+    assert_eq!(gb.read_memory(0xfff0), 0xff);
+    // The byte being copied instead of the ROM's EE
+    let conflict = gb.read_memory(0xfff1);
+    assert!(conflict < 0xa0, "read {conflict:02x}");
+}
+
+#[test]
+fn restart_copies_the_new_source() {
+    #[rustfmt::skip]
+    let mut routine = vec![
+        0x3e, 0xc1, 0xe0, 0x46, // LD A,C1; LDH (46),A
+        0x3e, 0x10, 0x3d,       // LD A,10; wait: DEC A
+        0x20, 0xfd,             // JR NZ,wait
+        0x3e, 0xc2, 0xe0, 0x46, // LD A,C2; LDH (46),A
+        // Still locked out while the new transfer starts up
+        0xfa, 0x00, 0xfe,       // LD A,(FE00)
+        0xe0, 0xf0,             // LDH (F0),A
+    ];
+    routine.extend(WAIT_AND_RETURN);
+    let gb = run(&routine);
+    assert_eq!(gb.read_memory(0xfff0), 0xff);
+    assert_eq!(oam(&gb), (0..0xa0).map(|i| !i).collect::<Vec<u8>>());
+}
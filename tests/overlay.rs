@@ -0,0 +1,40 @@
+// The frontend's error overlay, drawn over a mid-grey frame
+
+#[path = "../src/frontend/overlay.rs"]
+mod overlay;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+// Rows of character cells that have any text in them
+fn lines_drawn(text: &str) -> Vec<usize> {
+    let mut frame = vec![0x80; WIDTH * HEIGHT * 4];
+    overlay::draw_message(&mut frame, WIDTH, text);
+    let lit = |x: usize, y: usize| frame[(y * WIDTH + x) * 4] == 0xff;
+    for (x, y) in (0..WIDTH).flat_map(|x| (0..HEIGHT).map(move |y| (x, y))) {
+        let pixel = &frame[(y * WIDTH + x) * 4..][..4];
+        assert!(pixel == [0xff; 4] || pixel == [0x20, 0x20, 0x20, 0x80]);
+        // Nothing in the margins
+        if lit(x, y) {
+            assert!((4..WIDTH - 4).contains(&x) && y >= 4, "{x},{y}");
+        }
+    }
+    (0..(HEIGHT - 4) / 9)
+        .filter(|row| (0..WIDTH).any(|x| (0..7).any(|y| lit(x, 4 + row * 9 + y))))
+        .collect()
+}
+
+#[test]
+fn wraps_at_spaces_and_newlines() {
+    assert_eq!(lines_drawn("Illegal opcode"), [0]);
+    // 25 characters fit on a line
+    assert_eq!(lines_drawn("Illegal opcode DD at 0150"), [0]);
+    assert_eq!(lines_drawn("Illegal opcode DD at 0150 in bank 1"), [0, 1]);
+    assert_eq!(lines_drawn("Illegal opcode\n\nLoad a savestate"), [0, 2]);
+    assert_eq!(lines_drawn(&"x".repeat(60)), [0, 1, 2]);
+}
+
+#[test]
+fn text_past_the_bottom_is_cut_off() {
+    assert_eq!(lines_drawn(&"x\n".repeat(30)), (0..15).collect::<Vec<_>>());
+}
@@ -0,0 +1,92 @@
+// The frontend's rewind buffer, driven by a ROM that keeps rewriting WRAM with
+// hard to compress data so every snapshot differs
+
+#[allow(dead_code)]
+#[path = "../src/frontend/rewind.rs"]
+mod rewind;
+
+mod common;
+
+use rewind::Rewind;
+use rgb::Gameboy;
+use std::time::{Duration, Instant};
+
+fn gameboy() -> Gameboy {
+    #[rustfmt::skip]
+    let program = [
+        0x21, 0x00, 0xc0,       // start: LD HL,C000
+        0x3e, 0x01,             // LD A,01
+        0x47, 0x87, 0x87, 0x80, // fill: LD B,A; ADD A,A; ADD A,A; ADD A,B
+        0x3c, 0x47,             // INC A; LD B,A
+        0xf0, 0x04, 0xa8, 0x22, // LDH A,(04); XOR B; LD (HL+),A
+        0x7c, 0xfe, 0xe0,       // LD A,H; CP E0
+        0x78, 0x20, 0xf0,       // LD A,B; JR NZ,fill
+        0x18, 0xe9,             // JR start
+    ];
+    Gameboy::new(common::rom(&program), None).unwrap()
+}
+
+#[test]
+fn step_back_restores_the_previous_frame() {
+    let mut gb = gameboy();
+    let mut rewind = Rewind::new(16);
+    let mut states = Vec::new();
+    for _ in 0..10 {
+        gb.run_frame().unwrap();
+        rewind.push(&gb);
+        states.push(gb.savestate());
+    }
+    rewind.step_back(&mut gb).unwrap();
+    assert_eq!(gb.savestate(), states[8]);
+    rewind.step_back(&mut gb).unwrap();
+    assert_eq!(gb.savestate(), states[7]);
+}
+
+// These back the costs quoted in rewind.rs. Run them with
+// `cargo test --release --test rewind -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn push_takes_a_fraction_of_a_frame() {
+    let mut gb = gameboy();
+    let mut rewind = Rewind::new(64);
+    let mut elapsed = Duration::ZERO;
+    let frames = 600;
+    for _ in 0..frames {
+        gb.run_frame().unwrap();
+        let start = Instant::now();
+        rewind.push(&gb);
+        elapsed += start.elapsed();
+    }
+    let per_frame = elapsed / frames;
+    println!("Rewind::push: {per_frame:?} per frame");
+    // A frame is 16.7 ms
+    assert!(per_frame < Duration::from_micros(500), "{per_frame:?}");
+}
+
+// Backs rewind.rs skipping the backup copy `load_savestate` makes
+#[test]
+#[ignore]
+fn load_snapshot_is_cheaper_than_load_savestate() {
+    let mut gb = gameboy();
+    gb.run_frame().unwrap();
+    let state = gb.savestate();
+    let mut savestate = Duration::ZERO;
+    let mut snapshot = Duration::ZERO;
+    for _ in 0..600 {
+        let start = Instant::now();
+        gb.load_savestate(&state).unwrap();
+        savestate += start.elapsed();
+        let start = Instant::now();
+        gb.load_snapshot(&state).unwrap();
+        snapshot += start.elapsed();
+    }
+    println!(
+        "load_savestate: {:?}, load_snapshot: {:?}",
+        savestate / 600,
+        snapshot / 600
+    );
+    assert!(
+        snapshot * 5 < savestate * 4,
+        "{snapshot:?} vs {savestate:?}"
+    );
+}
@@ -0,0 +1,85 @@
+// Loading battery-backed saves, including ones written by older versions, and
+// when games ask for them to be written
+
+mod common;
+
+use rgb::Gameboy;
+
+const RAM_SIZE: usize = 0x2000;
+
+// MBC3+TIMER+RAM+BATTERY with 8 KiB of RAM
+fn mbc3_rtc() -> Gameboy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x10;
+    rom[0x149] = 0x02;
+    Gameboy::new(rom, None).unwrap()
+}
+
+fn ram() -> Vec<u8> {
+    (0..RAM_SIZE).map(|i| i as u8).collect()
+}
+
+// The registers in a VBA/BGB footer, one per u32
+fn footer_regs(footer: &[u8]) -> Vec<u8> {
+    footer[..40].chunks(4).map(|reg| reg[0]).collect()
+}
+
+#[test]
+fn legacy_rtc_footer_is_migrated() {
+    let mut gb = mbc3_rtc();
+    let mut save = ram();
+    // Current and latched S, M, H, days low, days high
+    save.extend([5, 6, 7, 0x23, 0x01, 1, 2, 3, 0x04, 0x00]);
+    gb.load_external_ram(&mut save.as_slice()).unwrap();
+
+    let mut saved = Vec::new();
+    gb.save_external_ram(&mut saved).unwrap();
+    assert_eq!(saved.len(), RAM_SIZE + 48);
+    assert_eq!(saved[..RAM_SIZE], ram());
+    assert_eq!(
+        footer_regs(&saved[RAM_SIZE..]),
+        [5, 6, 7, 0x23, 0x01, 1, 2, 3, 0x04, 0x00]
+    );
+}
+
+#[test]
+fn unknown_rtc_footer_keeps_ram() {
+    let mut gb = mbc3_rtc();
+    let mut save = ram();
+    save.extend([0xff; 7]);
+    gb.load_external_ram(&mut save.as_slice()).unwrap();
+
+    let mut saved = Vec::new();
+    gb.save_external_ram(&mut saved).unwrap();
+    assert_eq!(saved[..RAM_SIZE], ram());
+    assert_eq!(footer_regs(&saved[RAM_SIZE..]), [0; 10]);
+}
+
+// MBC2+RAM+BATTERY running `program` after enabling RAM and writing to it
+fn mbc2_flush_requested(program: &[u8]) -> bool {
+    #[rustfmt::skip]
+    let mut code = vec![
+        0x3e, 0x0a, 0xea, 0x00, 0x00, // LD A,0A; LD (0000),A
+        0xea, 0x00, 0xa0,             // LD (A000),A
+    ];
+    code.extend(program);
+    code.extend([0x18, 0xfe]); // JR $
+    let mut rom = common::rom(&code);
+    rom[0x147] = 0x06;
+    let mut gb = Gameboy::new(rom, None).unwrap();
+    common::run_frames(&mut gb, 1);
+    gb.take_external_ram_flush()
+}
+
+#[test]
+fn disabling_ram_requests_a_flush() {
+    // LD A,00; LD (0000),A
+    assert!(mbc2_flush_requested(&[0x3e, 0x00, 0xea, 0x00, 0x00]));
+}
+
+// MBC2 selects the ROM bank through 0000-3FFF with address bit 8 set
+#[test]
+fn mbc2_rom_bank_write_keeps_ram_enabled() {
+    // LD A,01; LD (0100),A
+    assert!(!mbc2_flush_requested(&[0x3e, 0x01, 0xea, 0x00, 0x01]));
+}
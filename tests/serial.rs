@@ -0,0 +1,64 @@
+// Internal clock serial transfers with nothing plugged in, timed by counting
+// polls of SC until the transfer finishes. The count goes to C000, and SC as
+// read back after writing 01 goes to C001. Returns those and KEY1.
+
+mod common;
+
+use rgb::Gameboy;
+
+fn run(sc: u8, cgb: bool, double_speed: bool) -> (u8, u8, u8) {
+    #[rustfmt::skip]
+    let mut program = vec![
+        0x3e, sc, 0xe0, 0x02,   // LD A,sc; LDH (02),A
+        0x06, 0x00,             // LD B,00
+        0x04, 0xf0, 0x02,       // wait: INC B; LDH A,(02)
+        0xcb, 0x7f, 0x20, 0xf9, // BIT 7,A; JR NZ,wait
+        0x78, 0xea, 0x00, 0xc0, // LD A,B; LD (C000),A
+        0x3e, 0x01, 0xe0, 0x02, // LD A,01; LDH (02),A
+        0xf0, 0x02, 0xea, 0x01, 0xc0, // LDH A,(02); LD (C001),A
+        0x18, 0xfe,             // JR $
+    ];
+    if double_speed {
+        // LD A,01; LDH (4D),A; STOP
+        program.splice(0..0, [0x3e, 0x01, 0xe0, 0x4d, 0x10, 0x00]);
+    }
+    let mut rom = common::rom(&program);
+    if cgb {
+        rom[0x143] = 0x80;
+    }
+    let mut gb = Gameboy::new(rom, None).unwrap();
+    common::run_frames(&mut gb, 2);
+    [0xc000, 0xc001, 0xff4d]
+        .map(|addr| gb.read_memory(addr))
+        .into()
+}
+
+#[test]
+fn dmg_ignores_the_fast_clock_bit() {
+    let (polls, sc, _) = run(0x83, false, false);
+    // 1024 mcycles at 9 per poll
+    assert!((110..=120).contains(&polls), "{polls} polls");
+    assert_eq!(sc, 0x7f);
+}
+
+#[test]
+fn cgb_fast_clock() {
+    let (polls, sc, _) = run(0x83, true, false);
+    // 32 mcycles
+    assert!((3..=5).contains(&polls), "{polls} polls");
+    assert_eq!(sc, 0x7d);
+
+    let (polls, _, _) = run(0x81, true, false);
+    assert!((110..=120).contains(&polls), "{polls} polls");
+}
+
+// Both clocks come off the system counter, so double speed doubles them too
+// and a transfer takes as many mcycles as before
+#[test]
+fn cgb_double_speed_clocks_follow_the_cpu() {
+    for sc in [0x81, 0x83] {
+        let (polls, _, key1) = run(sc, true, true);
+        assert_eq!(key1 & 0x80, 0x80);
+        assert_eq!(polls, run(sc, true, false).0, "SC={sc:02x}");
+    }
+}
@@ -0,0 +1,171 @@
+// Runs the SM83 single instruction tests (https://github.com/SingleStepTests/sm83)
+// against the CPU on a flat 64 KiB bus. Each test gives the CPU state and RAM
+// before and after one instruction, and the memory access made on every
+// M-cycle. The vectors aren't distributed with the repo, so this is ignored
+// by default. Fetch them into tests/sm83 with tests/fetch-sm83.sh, then run
+// `cargo test --test sm83 -- --ignored`. Missing vectors fail.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use rgb::{Access, Cpu, CpuRegisters, FlatBus};
+use serde_json::Value;
+
+struct State {
+    registers: CpuRegisters,
+    ime: bool,
+    ie: Option<u8>,
+    ram: Vec<(u16, u8)>,
+}
+
+fn field<T: TryFrom<u64>>(value: &Value, name: &str) -> Result<T> {
+    value[name]
+        .as_u64()
+        .and_then(|n| T::try_from(n).ok())
+        .with_context(|| format!("bad or missing `{name}`"))
+}
+
+fn parse_state(value: &Value) -> Result<State> {
+    let registers = CpuRegisters {
+        a: field(value, "a")?,
+        f: field(value, "f")?,
+        b: field(value, "b")?,
+        c: field(value, "c")?,
+        d: field(value, "d")?,
+        e: field(value, "e")?,
+        h: field(value, "h")?,
+        l: field(value, "l")?,
+        sp: field(value, "sp")?,
+        pc: field(value, "pc")?,
+    };
+    let ram = value["ram"]
+        .as_array()
+        .context("missing `ram`")?
+        .iter()
+        .map(|entry| match (entry[0].as_u64(), entry[1].as_u64()) {
+            (Some(addr), Some(val)) => Ok((addr as u16, val as u8)),
+            _ => bail!("bad ram entry {entry}"),
+        })
+        .collect::<Result<_>>();
+    Ok(State {
+        registers,
+        ime: field::<u8>(value, "ime")? != 0,
+        ie: value.get("ie").map(|_| field(value, "ie")).transpose()?,
+        ram: ram?,
+    })
+}
+
+// Entries are `[addr, value, flags]`, where flags is "r-m" for a read, "-wm"
+// for a write and "---" for a cycle without a memory access
+fn parse_cycle(value: &Value) -> Result<Option<(u16, u8, Access)>> {
+    let Some(flags) = value.get(2).and_then(Value::as_str) else {
+        return Ok(None);
+    };
+    let access = if flags.contains('r') {
+        Access::Read
+    } else if flags.contains('w') {
+        Access::Write
+    } else {
+        return Ok(None);
+    };
+    let addr = value[0].as_u64().context("bad cycle address")?;
+    let val = value[1].as_u64().context("bad cycle value")?;
+    Ok(Some((addr as u16, val as u8, access)))
+}
+
+fn run_test(test: &Value) -> Result<()> {
+    let initial = parse_state(&test["initial"]).context("initial")?;
+    let expected = parse_state(&test["final"]).context("final")?;
+    let expected_cycles = test["cycles"]
+        .as_array()
+        .context("missing `cycles`")?
+        .iter()
+        .map(parse_cycle)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut bus = FlatBus::default();
+    if let Some(ie) = initial.ie {
+        bus.ram[0xffff] = ie;
+    }
+    for &(addr, val) in &initial.ram {
+        bus.ram[addr as usize] = val;
+    }
+    let mut cpu = Cpu::with_bus(bus);
+    cpu.set_registers(initial.registers);
+    cpu.set_ime(initial.ime);
+    cpu.step()?;
+
+    let registers = cpu.registers();
+    if registers != expected.registers {
+        bail!(
+            "registers\n  expected {:x?}\n  got      {registers:x?}",
+            expected.registers
+        );
+    }
+    if cpu.ime() != expected.ime {
+        bail!("expected IME {}, got {}", expected.ime, cpu.ime());
+    }
+    let bus = cpu.memory();
+    if let Some(ie) = expected.ie
+        && bus.ram[0xffff] != ie
+    {
+        bail!("expected IE {ie:02x}, got {:02x}", bus.ram[0xffff]);
+    }
+    for &(addr, val) in &expected.ram {
+        let actual = bus.ram[addr as usize];
+        if actual != val {
+            bail!("expected {val:02x} at {addr:04x}, got {actual:02x}");
+        }
+    }
+    if bus.cycles != expected_cycles {
+        bail!(
+            "bus activity\n  expected {expected_cycles:x?}\n  got      {:x?}",
+            bus.cycles
+        );
+    }
+    Ok(())
+}
+
+fn run_file(path: &Path) -> Result<usize> {
+    let json = std::fs::read_to_string(path)?;
+    let tests: Vec<Value> = serde_json::from_str(&json)?;
+    for test in &tests {
+        let name = test["name"].as_str().unwrap_or("?");
+        run_test(test).with_context(|| format!("test \"{name}\""))?;
+    }
+    Ok(tests.len())
+}
+
+#[test]
+#[ignore = "needs the test vectors from tests/fetch-sm83.sh"]
+fn sm83() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83");
+    let entries = std::fs::read_dir(&dir).unwrap_or_else(|e| panic!("{}: {e}", dir.display()));
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no test vectors in {}", dir.display());
+
+    let mut failures = Vec::new();
+    let mut passed = 0;
+    for path in &paths {
+        match run_file(path) {
+            Ok(count) => passed += count,
+            Err(e) => failures.push(format!(
+                "{}: {e:#}",
+                path.file_name().unwrap().to_string_lossy()
+            )),
+        }
+    }
+    eprintln!("{passed} tests passed in {} files", paths.len());
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} files failed:\n{}",
+            failures.len(),
+            paths.len(),
+            failures.join("\n")
+        );
+    }
+}
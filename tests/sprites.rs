@@ -0,0 +1,45 @@
+// Sprite selection at the edges of the OAM coordinate range
+
+mod common;
+
+use rgb::Gameboy;
+
+const WIDTH: usize = 160;
+
+// Y of F0 and above puts an 8x16 sprite's bottom edge past FF. Sprite 0 has
+// Y=F8 at x=8 and must neither be drawn nor use up one of the ten slots on
+// lines 0-15, where sprites 1-10 sit at x=0, 16, ..., 144 with a solid tile.
+#[test]
+fn sprite_with_y_past_f0_is_skipped() {
+    #[rustfmt::skip]
+    let program = [
+        0xaf, 0xe0, 0x40,       // XOR A; LDH (40),A
+        0x21, 0x00, 0x80,       // LD HL,8000
+        0x3e, 0xff, 0x06, 0x20, // LD A,FF; LD B,20
+        0x22, 0x05, 0x20, 0xfc, // tile: LD (HL+),A; DEC B; JR NZ,tile
+        0x21, 0x00, 0xfe,       // LD HL,FE00
+        0x3e, 0xf8, 0x22,       // LD A,F8; LD (HL+),A
+        0x3e, 0x10, 0x22,       // LD A,10; LD (HL+),A
+        0xaf, 0x22, 0x22,       // XOR A; LD (HL+),A; LD (HL+),A
+        0x06, 0x0a, 0x0e, 0x08, // LD B,0A; LD C,08
+        0x3e, 0x10, 0x22,       // sprite: LD A,10; LD (HL+),A
+        0x79, 0x22,             // LD A,C; LD (HL+),A
+        0xc6, 0x10, 0x4f,       // ADD A,10; LD C,A
+        0xaf, 0x22, 0x22,       // XOR A; LD (HL+),A; LD (HL+),A
+        0x05, 0x20, 0xf2,       // DEC B; JR NZ,sprite
+        0x3e, 0xe4, 0xe0, 0x48, // LD A,E4; LDH (48),A
+        // LCD and 8x16 sprites on, background off
+        0x3e, 0x96, 0xe0, 0x40, // LD A,96; LDH (40),A
+        0x18, 0xfe,             // JR $
+    ];
+    let mut gb = Gameboy::new(common::rom(&program), None).unwrap();
+    common::run_frames(&mut gb, 3);
+    let shades = gb.framebuffer_shades();
+    for y in 0..16 {
+        for x in 0..WIDTH {
+            let expected = if x % 16 < 8 { 3 } else { 0 };
+            assert_eq!(shades[y * WIDTH + x], expected, "pixel {x},{y}");
+        }
+    }
+    assert!(shades[16 * WIDTH..].iter().all(|&shade| shade == 0));
+}
@@ -0,0 +1,209 @@
+// Conformance tests against the blargg and mooneye test suites, plus the
+// dmg-acid2 rendering test. The ROMs aren't distributed with the repo, so
+// these are ignored by default. Fetch them into tests/roms with
+// tests/fetch-roms.sh, then run `cargo test -- --ignored`. A missing ROM fails.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{Context, Result, bail};
+use rgb::{Gameboy, SCREEN_HEIGHT, SCREEN_WIDTH, SerialDevice};
+
+#[derive(Copy, Clone)]
+enum Check {
+    // blargg: the result is printed over serial
+    Serial,
+    // mooneye: LD B,B once done, with B/C/D/E/H/L set to the Fibonacci
+    // sequence on success
+    Fibonacci,
+    // LD B,B once done, after which the screen must match a reference image
+    Screenshot(&'static str),
+}
+
+struct SerialCapture(Rc<RefCell<Vec<u8>>>);
+
+impl SerialDevice for SerialCapture {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        self.0.borrow_mut().push(byte);
+        Some(0xff)
+    }
+
+    fn poll_external(&mut self, _: u8) -> Option<u8> {
+        None
+    }
+}
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms")
+}
+
+fn run_rom(path: &str, check: Check, timeout: u32) {
+    let path = roms_dir().join(path);
+    let rom = std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    if let Err(e) = run(rom, check, timeout) {
+        panic!("{}: {e:#}", path.display());
+    }
+}
+
+fn run(rom: Vec<u8>, check: Check, timeout: u32) -> Result<()> {
+    let mut gb = Gameboy::new(rom, None)?;
+    let output = Rc::new(RefCell::new(Vec::new()));
+    gb.connect_serial(Box::new(SerialCapture(output.clone())));
+
+    for _ in 0..timeout {
+        gb.run_frame()?;
+        gb.audio_samples().for_each(drop);
+        match check {
+            Check::Serial => {
+                let output = String::from_utf8_lossy(&output.borrow()).into_owned();
+                if output.contains("Passed") {
+                    return Ok(());
+                } else if output.contains("Failed") {
+                    bail!("{}", output.trim());
+                }
+            }
+            Check::Fibonacci if gb.take_breakpoint() => {
+                let regs = gb.registers();
+                let fib = [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+                if fib == [3, 5, 8, 13, 21, 34] {
+                    return Ok(());
+                }
+                bail!("failed with {regs:x?}");
+            }
+            Check::Screenshot(reference) if gb.take_breakpoint() => {
+                // Let the frame being drawn finish
+                gb.run_frame()?;
+                return compare_screenshot(&gb, reference);
+            }
+            _ => {}
+        }
+    }
+    bail!("timed out after {timeout} frames");
+}
+
+// Reference images use the same four grays as the emulator, so comparing the
+// first channel of each pixel is enough.
+fn compare_screenshot(gb: &Gameboy, reference: &str) -> Result<()> {
+    let path = roms_dir().join(reference);
+    let file = File::open(&path).with_context(|| format!("{}", path.display()))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    if (info.width, info.height) != (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32) {
+        bail!("reference image is {}x{}", info.width, info.height);
+    }
+    let channels = info.color_type.samples();
+
+    let actual = gb.framebuffer().chunks_exact(4).map(|pixel| pixel[0]);
+    let expected = buf.chunks_exact(channels).map(|pixel| pixel[0]);
+    let mismatches: Vec<usize> = actual
+        .zip(expected)
+        .enumerate()
+        .filter(|(_, (a, e))| a != e)
+        .map(|(i, _)| i)
+        .collect();
+    if let Some(&first) = mismatches.first() {
+        bail!(
+            "{} pixels differ from {reference}, first at ({}, {})",
+            mismatches.len(),
+            first % SCREEN_WIDTH,
+            first / SCREEN_WIDTH
+        );
+    }
+    Ok(())
+}
+
+macro_rules! rom_tests {
+    ($check:expr, $timeout:expr; $($name:ident => $path:literal,)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the test ROMs from tests/fetch-roms.sh"]
+            fn $name() {
+                super::run_rom($path, $check, $timeout);
+            }
+        )*
+    };
+}
+
+mod blargg {
+    use super::Check;
+
+    rom_tests! { Check::Serial, 2000;
+        cpu_instrs_01 => "blargg/cpu_instrs/individual/01-special.gb",
+        cpu_instrs_02 => "blargg/cpu_instrs/individual/02-interrupts.gb",
+        cpu_instrs_03 => "blargg/cpu_instrs/individual/03-op sp,hl.gb",
+        cpu_instrs_04 => "blargg/cpu_instrs/individual/04-op r,imm.gb",
+        cpu_instrs_05 => "blargg/cpu_instrs/individual/05-op rp.gb",
+        cpu_instrs_06 => "blargg/cpu_instrs/individual/06-ld r,r.gb",
+        cpu_instrs_07 => "blargg/cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+        cpu_instrs_08 => "blargg/cpu_instrs/individual/08-misc instrs.gb",
+        cpu_instrs_09 => "blargg/cpu_instrs/individual/09-op r,r.gb",
+        cpu_instrs_10 => "blargg/cpu_instrs/individual/10-bit ops.gb",
+        cpu_instrs_11 => "blargg/cpu_instrs/individual/11-op a,(hl).gb",
+        instr_timing => "blargg/instr_timing/instr_timing.gb",
+        mem_timing_01 => "blargg/mem_timing/individual/01-read_timing.gb",
+        mem_timing_02 => "blargg/mem_timing/individual/02-write_timing.gb",
+        mem_timing_03 => "blargg/mem_timing/individual/03-modify_timing.gb",
+        oam_bug_01 => "blargg/oam_bug/rom_singles/1-lcd_sync.gb",
+        oam_bug_02 => "blargg/oam_bug/rom_singles/2-causes.gb",
+        oam_bug_03 => "blargg/oam_bug/rom_singles/3-non_causes.gb",
+        oam_bug_08 => "blargg/oam_bug/rom_singles/8-instr_effect.gb",
+    }
+}
+
+mod mooneye {
+    use super::Check;
+
+    rom_tests! { Check::Fibonacci, 600;
+        add_sp_e_timing => "mooneye/acceptance/add_sp_e_timing.gb",
+        boot_regs => "mooneye/acceptance/boot_regs-dmgABC.gb",
+        call_timing => "mooneye/acceptance/call_timing.gb",
+        div_timing => "mooneye/acceptance/div_timing.gb",
+        ei_sequence => "mooneye/acceptance/ei_sequence.gb",
+        ei_timing => "mooneye/acceptance/ei_timing.gb",
+        halt_ime0_ei => "mooneye/acceptance/halt_ime0_ei.gb",
+        halt_ime0_nointr_timing => "mooneye/acceptance/halt_ime0_nointr_timing.gb",
+        halt_ime1_timing => "mooneye/acceptance/halt_ime1_timing.gb",
+        if_ie_registers => "mooneye/acceptance/if_ie_registers.gb",
+        intr_timing => "mooneye/acceptance/intr_timing.gb",
+        jp_timing => "mooneye/acceptance/jp_timing.gb",
+        ld_hl_sp_e_timing => "mooneye/acceptance/ld_hl_sp_e_timing.gb",
+        oam_dma_restart => "mooneye/acceptance/oam_dma_restart.gb",
+        oam_dma_start => "mooneye/acceptance/oam_dma_start.gb",
+        oam_dma_timing => "mooneye/acceptance/oam_dma_timing.gb",
+        pop_timing => "mooneye/acceptance/pop_timing.gb",
+        push_timing => "mooneye/acceptance/push_timing.gb",
+        rapid_di_ei => "mooneye/acceptance/rapid_di_ei.gb",
+        ret_timing => "mooneye/acceptance/ret_timing.gb",
+        reti_intr_timing => "mooneye/acceptance/reti_intr_timing.gb",
+        rst_timing => "mooneye/acceptance/rst_timing.gb",
+        bits_mem_oam => "mooneye/acceptance/bits/mem_oam.gb",
+        bits_reg_f => "mooneye/acceptance/bits/reg_f.gb",
+        instr_daa => "mooneye/acceptance/instr/daa.gb",
+        interrupts_ie_push => "mooneye/acceptance/interrupts/ie_push.gb",
+        oam_dma_basic => "mooneye/acceptance/oam_dma/basic.gb",
+        oam_dma_reg_read => "mooneye/acceptance/oam_dma/reg_read.gb",
+        ppu_stat_irq_blocking => "mooneye/acceptance/ppu/stat_irq_blocking.gb",
+        timer_div_write => "mooneye/acceptance/timer/div_write.gb",
+        timer_rapid_toggle => "mooneye/acceptance/timer/rapid_toggle.gb",
+        timer_tim00 => "mooneye/acceptance/timer/tim00.gb",
+        timer_tim01 => "mooneye/acceptance/timer/tim01.gb",
+        timer_tim10 => "mooneye/acceptance/timer/tim10.gb",
+        timer_tim11 => "mooneye/acceptance/timer/tim11.gb",
+        timer_tima_reload => "mooneye/acceptance/timer/tima_reload.gb",
+        timer_tima_write_reloading => "mooneye/acceptance/timer/tima_write_reloading.gb",
+        timer_tma_write_reloading => "mooneye/acceptance/timer/tma_write_reloading.gb",
+    }
+}
+
+mod acid2 {
+    use super::Check;
+
+    rom_tests! { Check::Screenshot("dmg-acid2/reference-dmg.png"), 600;
+        dmg_acid2 => "dmg-acid2/dmg-acid2.gb",
+    }
+}